@@ -0,0 +1,142 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc,RwLock};
+
+use blockdag::{Node,BlockRaw,Simulator,node_add_block};
+
+/// When the attacker publishes its withheld blocks.
+///
+#[derive(Clone, Debug)]
+pub enum AttackRelease{
+    AtTime(u64),                                            // virtual time (ms) of the release.
+    AfterBlocks(u64),                                       // release once this number of private blocks are mined.
+}
+
+#[derive(Clone, Debug)]
+pub struct AttackerConfig{
+    pub hash_fraction: f64,                                 // attacker (or coalition) share of the hash power, i.e. the probability it mines the next block.
+    pub release: AttackRelease,
+}
+
+/// The attacker of a simulation: a private node which only knows Genesis and its own blocks, mining on its own tips
+/// and withholding all its blocks until the release. After the release, the attacker stops mining.
+///
+pub struct Attacker{
+    pub config: AttackerConfig,
+    pub node: Arc<RwLock<Node>>,
+    pub blocks: Vec<String>,                                // attacker blocks, in mining order.
+    pub withheld: Vec<BlockRaw>,                            // blocks not released yet, in mining order.
+    pub released_at: Option<u64>,
+    pub honest_blue_at_release: Vec<HashSet<String>>,       // for each honest node, the honest blocks which were blue at the release.
+}
+
+impl Attacker {
+
+    pub fn new(config: AttackerConfig, k: i32) -> Attacker{
+
+        let node = Node::init("attacker");
+        node_add_block("Genesis", &Vec::new(), &mut node.write().unwrap(), k, true);
+
+        Attacker{
+            config,
+            node,
+            blocks: Vec::new(),
+            withheld: Vec::new(),
+            released_at: None,
+            honest_blue_at_release: Vec::new(),
+        }
+    }
+
+    /// The attacker mines as long as it has not released its blocks.
+    ///
+    pub fn is_active(&self) -> bool{
+        self.released_at.is_none()
+    }
+
+    pub fn release_due(&self, clock: u64) -> bool{
+        match self.config.release {
+            AttackRelease::AtTime(time) => clock >= time,
+            AttackRelease::AfterBlocks(blocks) => self.blocks.len() as u64 >= blocks,
+        }
+    }
+}
+
+/// Outcome of an attack, summed over all the honest nodes.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AttackReport{
+    pub hash_fraction: f64,
+    pub attacker_blocks: u64,
+    pub released_at: Option<u64>,
+    pub honest_nodes: u64,
+    pub honest_blue_at_release: u64,                        // (node, honest block) pairs blue at the release.
+    pub honest_turned_red: u64,                             // (node, honest block) pairs blue at the release, red at the end.
+    pub attacker_blue: u64,                                 // (node, attacker block) pairs blue at the end.
+    pub honest_turned_red_fraction: f64,
+    pub attacker_blue_fraction: f64,
+}
+
+impl fmt::Display for AttackReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "attack: hash_fraction={},attacker_blocks={},released_at={},honest_turned_red={}/{} ({:.4}),attacker_blue={} ({:.4})",
+               self.hash_fraction, self.attacker_blocks, self.released_at.map_or(String::from("none"), |t| format!("{}ms", t)),
+               self.honest_turned_red, self.honest_blue_at_release, self.honest_turned_red_fraction,
+               self.attacker_blue, self.attacker_blue_fraction)
+    }
+}
+
+/// Function providing the attack report of a finished simulation, or None if the simulation has no attacker.
+///
+pub fn sim_attack_report(sim: &Simulator) -> Option<AttackReport>{
+
+    let attacker = sim.attacker.as_ref()?;
+
+    let mut honest_blue_at_release: u64 = 0;
+    let mut honest_turned_red: u64 = 0;
+    let mut attacker_blue: u64 = 0;
+
+    for (number, node) in sim.nodes.iter().enumerate() {
+        let node_r = node.read().unwrap();
+
+        if let Some(blues) = attacker.honest_blue_at_release.get(number) {
+            honest_blue_at_release += blues.len() as u64;
+            honest_turned_red += blues.iter().filter(|name| !node_r.dag.get(*name).unwrap().read().unwrap().is_blue).count() as u64;
+        }
+
+        attacker_blue += attacker.blocks.iter().filter(|name| {
+            node_r.dag.get(*name).is_some_and(|block| block.read().unwrap().is_blue)
+        }).count() as u64;
+    }
+
+    let honest_nodes = sim.nodes.len() as u64;
+    let attacker_blocks = attacker.blocks.len() as u64;
+
+    Some(AttackReport{
+        hash_fraction: attacker.config.hash_fraction,
+        attacker_blocks,
+        released_at: attacker.released_at,
+        honest_nodes,
+        honest_blue_at_release,
+        honest_turned_red,
+        attacker_blue,
+        honest_turned_red_fraction: if honest_blue_at_release == 0 { 0.0 } else { honest_turned_red as f64 / honest_blue_at_release as f64 },
+        attacker_blue_fraction: if attacker_blocks * honest_nodes == 0 { 0.0 } else { attacker_blue as f64 / (attacker_blocks * honest_nodes) as f64 },
+    })
+}
@@ -21,6 +21,8 @@ use std::sync::{Arc,RwLock};
 use std::fmt;
 use std::cmp::Ordering;
 
+use blockdag::Transaction;
+
 /// Structure providing fast access to block data.
 ///
 pub struct Block{
@@ -33,14 +35,36 @@ pub struct Block{
     pub prev: HashMap<String, Arc<RwLock<Block>>>,          // bits stream.
     pub next: HashMap<String, Arc<RwLock<Block>>>,          // local generated, not in bits stream transmitting
     pub tips_snapshot: HashMap<String, Arc<RwLock<Block>>>, // local generated. a snapshot of tips at the time of block added to the local dag. after added.
+    pub sequence: u64,                                      // local generated. the arrival order of this block in the local dag.
+    pub timestamp: u64,                                     // bits stream. ms, set by the miner.
+    pub target: u64,                                        // bits stream. the header hash must not exceed it, 0 for no proof of work.
+    pub nonce: u64,                                         // bits stream.
+    pub merkle_root: [u8; 32],                              // bits stream, local verified. the root of the transaction hashes.
+    pub miner: Option<[u8; 32]>,                            // bits stream. the public key of the miner, None for an anonymous block.
+    pub signature: Option<Vec<u8>>,                         // bits stream, local verified. by the miner, over the header digest.
+    pub txs: Vec<Transaction>,                              // bits stream. the payload.
 }
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BlockRaw{                                        // simulation of raw block data
     pub name: String,                                       // bits stream, local verified. that is: hash of block head. (in simulation we use a readable string)
     pub height: u64,                                        // bits stream, local verified.
     pub size_of_past_set: u64,                              // bits stream, local verified.
     pub prev: Vec<String>,                                  // bits stream.
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub target: u64,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub merkle_root: [u8; 32],
+    #[serde(default)]
+    pub miner: Option<[u8; 32]>,
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>,
+    #[serde(default)]
+    pub txs: Vec<Transaction>,
 }
 
 
@@ -66,6 +90,27 @@ impl fmt::Display for Block {
     }
 }
 
+impl Block {
+
+    /// The raw block data of this block, as it would be sent to the other nodes.
+    ///
+    pub fn to_raw(&self) -> BlockRaw{
+        BlockRaw{
+            name: self.name.clone(),
+            height: self.height,
+            size_of_past_set: self.size_of_past_set,
+            prev: sorted_keys_by_height(&self.prev, false).into_iter().map(|(n,_)| n).collect(),
+            timestamp: self.timestamp,
+            target: self.target,
+            nonce: self.nonce,
+            merkle_root: self.merkle_root,
+            miner: self.miner,
+            signature: self.signature.clone(),
+            txs: self.txs.clone(),
+        }
+    }
+}
+
 pub fn append_maps(target: &mut HashMap<String,Arc<RwLock<Block>>>, source: &HashMap<String,Arc<RwLock<Block>>>){
 
     for (key, value) in source {
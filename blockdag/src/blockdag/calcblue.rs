@@ -50,6 +50,11 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
         return;
     }
 
+    // blocks whose colour changes, for the consumers of 'Node::colour_log'.
+    let mut recoloured: Vec<String> = Vec::new();
+    // blocks whose score changes, for 'update_selected_chain'.
+    let mut rescored: Vec<String> = Vec::new();
+
     // step 2
     let score_stpq = get_stpq(tips);
 
@@ -67,6 +72,9 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
             let block = Arc::clone(value);
             let tip = &mut block.write().unwrap();
             if &tip.name != block_name {
+                if tip.is_blue {
+                    recoloured.push(tip.name.clone());
+                }
                 tip.is_blue = false;
                 tip.size_of_anticone_blue = -1;
             }
@@ -83,6 +91,9 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
                 // step 7
                 {
                     let mut block_w = dag.get(name).unwrap().write().unwrap();
+                    if !block_w.is_blue {
+                        recoloured.push(name.clone());
+                    }
                     block_w.is_blue = true;
                     block_w.size_of_anticone_blue = blues;
                     drop(block_w);
@@ -124,6 +135,7 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
                         let mut pred = dag.get(name).unwrap().write().unwrap();
                         pred.is_blue = true;
                         pred.size_of_anticone_blue = blues;
+                        recoloured.push(name.clone());
                         debug!("calc_blue(): step 7. block {}. add {} to the blue. size_of_anticone_blue={}", block_name, pred.name, blues);
 
                     }   // scope to limit the lifetime of 'write()' lock.
@@ -141,6 +153,9 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
             let (_,size_of_past_blue) = sizeof_pastset(&block.read().unwrap());
             {
                 let block_w = &mut block.write().unwrap();
+                if block_w.size_of_past_blue != size_of_past_blue {
+                    rescored.push(name.clone());
+                }
                 block_w.size_of_past_blue = size_of_past_blue;
             }
         }
@@ -167,6 +182,8 @@ pub fn calc_blue(block_name: &str, node: &mut Node, k: i32){
         }
     }
 
+    node.log_colours(recoloured);
+    node.rescored.extend(rescored);
 
 }
 
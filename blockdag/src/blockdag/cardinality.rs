@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU Lesser General Public License
 // along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap,HashSet};
 use std::collections::hash_map::Entry;
 use std::sync::{Arc,RwLock};
 
@@ -74,6 +74,7 @@ pub fn sizeof_pastset(block: &Block) -> (u64,u64){
     //println!("sizeof_pastset(): block={} bmax={} size_of_past={}", block.name, bmax_name, size_of_past);
 
     let mut used_rest: HashMap<String,bool> = HashMap::new();
+    let mut counted_rest: HashSet<String> = rest_pred_set.keys().cloned().collect();
     let mut used_maxi: HashMap<String,bool> = HashMap::new();
 
     let mut rest_maxmin = MaxMin{max:0, min:<u64>::max_value()};
@@ -104,7 +105,8 @@ pub fn sizeof_pastset(block: &Block) -> (u64,u64){
         //         size_of_past);
         let rest_keys = new_rest_pred.iter().map(|(k,_)|{k.clone()}).collect::<Vec<String>>();
         for name in &rest_keys {
-            if maxi_pred_set.get(name).is_some() {
+            // a block reached again by a longer path is already counted.
+            if maxi_pred_set.get(name).is_some() || !counted_rest.insert(name.clone()) {
                 new_rest_pred.remove(name);
             }
         }
@@ -0,0 +1,134 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::mpsc::{channel,Receiver};
+
+use blockdag::Node;
+
+/// A switch of the selected parent chain: the blocks removed from its top, the top one firstly, then the blocks added,
+/// the lowest one firstly.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChainChanged{
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+impl Node {
+
+    /// Function providing the selected parent of a block: its parent with the best score, ties broken as 'get_stpq' does.
+    /// None for Genesis or an unknown block.
+    ///
+    pub fn selected_parent(&self, name: &str) -> Option<String>{
+        let block = self.dag.get(name)?.read().unwrap();
+        let mut best: Option<(u64, u64, &String)> = None;
+        for (prev_name, prev) in &block.prev {
+            let prev = prev.read().unwrap();
+            let better = match best {
+                Some((blue, size, best_name)) => (prev.size_of_past_blue, prev.size_of_past_set) > (blue, size)
+                    || ((prev.size_of_past_blue, prev.size_of_past_set) == (blue, size) && prev_name < best_name),
+                None => true,
+            };
+            if better {
+                best = Some((prev.size_of_past_blue, prev.size_of_past_set, prev_name));
+            }
+        }
+        best.map(|(_, _, name)| name.clone())
+    }
+
+    /// The selected parent chain, from the selected parent of the virtual block back to Genesis.
+    ///
+    pub fn selected_chain(&self) -> Vec<String>{
+        self.chain.iter().rev().cloned().collect()
+    }
+
+    /// Receive a 'ChainChanged' each time a new block switches the selected parent chain.
+    ///
+    pub fn subscribe_chain(&mut self) -> Receiver<ChainChanged>{
+        let (tx, rx) = channel();
+        self.chain_subscribers.push(tx);
+        rx
+    }
+}
+
+/// Function providing the update of the selected parent chain after a new block, see 'Node::selected_chain'.
+///
+/// The new chain is walked back from the selected parent of the virtual block, until a block of the current chain whose
+/// selected parent is unchanged: only the top of the chain is replaced. But 'calc_blue' (step 9) rewrites the scores of
+/// some blocks, see 'Node::rescored', and the selected parent of their children may change, even deep in the chain:
+/// the walk doesn't stop at or above the lowest such child, so it goes down to the actual fork point.
+/// Return the change, if any, as notified.
+///
+pub fn update_selected_chain(node: &mut Node) -> Option<ChainChanged>{
+
+    let mut floor = node.chain.len();
+    for name in node.rescored.drain(..) {
+        if let Some(block) = node.dag.get(&name) {
+            for child in block.read().unwrap().next.keys() {
+                if let Some(&index) = node.chain_index.get(child) {
+                    floor = floor.min(index);
+                }
+            }
+        }
+    }
+
+    let mut added: Vec<String> = Vec::new();
+    let mut joint: Option<usize> = None;
+    let mut cursor = node.virtual_block.selected_parent.clone();
+
+    while let Some(name) = cursor {
+        let selected_parent = node.selected_parent(&name);
+        if let Some(&index) = node.chain_index.get(&name) {
+            if index == 0 || (index < floor && selected_parent.as_ref() == Some(&node.chain[index-1])) {
+                joint = Some(index);
+                break;
+            }
+        }
+        added.push(name);
+        cursor = selected_parent;
+    }
+
+    // the blocks walked which are still in the chain, at the same place, are kept.
+    let mut keep = joint.map_or(0, |index| index + 1);
+    while let Some(name) = added.last() {
+        if node.chain.get(keep) != Some(name) {
+            break;
+        }
+        added.pop();
+        keep += 1;
+    }
+
+    let removed: Vec<String> = node.chain.drain(keep..).rev().collect();
+    for name in &removed {
+        node.chain_index.remove(name);
+    }
+    for name in added.iter().rev() {
+        node.chain_index.insert(name.clone(), node.chain.len());
+        node.chain.push(name.clone());
+    }
+
+    if removed.is_empty() && added.is_empty() {
+        return None;
+    }
+    added.reverse();
+
+    let change = ChainChanged{removed, added};
+    if !change.removed.is_empty() {
+        debug!("update_selected_chain(): node={}. chain switch: {:?}", node.name, change);
+    }
+    node.chain_subscribers.retain(|tx| tx.send(change.clone()).is_ok());
+    Some(change)
+}
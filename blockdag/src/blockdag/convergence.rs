@@ -0,0 +1,121 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use blockdag::{Node,dag_order,sorted_keys_by_height};
+
+/// Result of comparing the views of several nodes, the first node being the reference.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConvergenceReport{
+    pub converged: bool,                                    // same blocks, same colours, same order and same tips on all nodes.
+    pub reference: String,
+    pub common_blocks: u64,                                 // blocks known by all nodes, the only ones compared.
+    pub first_divergent: Option<String>,                    // first block, in the reference order, where a node disagrees on colour or order.
+    pub disagreeing_nodes: Vec<String>,                     // nodes disagreeing with the reference on colour or order of a common block.
+    pub colour_mismatches: u64,                             // (node, block) pairs with a colour different from the reference.
+    pub incomplete_nodes: Vec<String>,                      // nodes which don't have all the blocks, i.e. still syncing.
+    pub tips_mismatch: Vec<String>,                         // nodes whose tips are not the same as the reference.
+}
+
+impl fmt::Display for ConvergenceReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.converged {
+            return write!(f, "converged. reference={},common_blocks={}", self.reference, self.common_blocks);
+        }
+
+        write!(f, "not converged. reference={},common_blocks={},first_divergent={},colour_mismatches={},disagreeing_nodes={:?},incomplete_nodes={:?},tips_mismatch={:?}",
+               self.reference, self.common_blocks, self.first_divergent.as_ref().map_or("none", |s| s.as_str()),
+               self.colour_mismatches, self.disagreeing_nodes, self.incomplete_nodes, self.tips_mismatch)
+    }
+}
+
+/// Function providing the consensus check among nodes: blue sets, blocks order and tips are compared to the first node's.
+///
+pub fn check_convergence(nodes: &[&Node]) -> ConvergenceReport{
+
+    let mut report = ConvergenceReport{
+        converged: true,
+        reference: nodes.first().map_or(String::new(), |n| n.name.clone()),
+        common_blocks: 0,
+        first_divergent: None,
+        disagreeing_nodes: Vec::new(),
+        colour_mismatches: 0,
+        incomplete_nodes: Vec::new(),
+        tips_mismatch: Vec::new(),
+    };
+
+    let reference = match nodes.first() {
+        Some(node) => node,
+        None => return report,
+    };
+
+    // only the blocks known by all the nodes can be compared.
+    let all_blocks: HashSet<&String> = nodes.iter().flat_map(|n| n.dag.keys()).collect();
+    let common: HashSet<&String> = all_blocks.iter().filter(|name| nodes.iter().all(|n| n.dag.contains_key(name.as_str()))).cloned().collect();
+    report.common_blocks = common.len() as u64;
+
+    for node in nodes {
+        if node.dag.len() < all_blocks.len() {
+            report.incomplete_nodes.push(node.name.clone());
+        }
+    }
+
+    let reference_order: Vec<String> = dag_order(&reference.dag).into_iter().filter(|n| common.contains(n)).collect();
+    let reference_tips = sorted_keys_by_height(&reference.tips, false);
+
+    let mut first_divergent_index: Option<usize> = None;
+
+    for node in &nodes[1..] {
+        let order: Vec<String> = dag_order(&node.dag).into_iter().filter(|n| common.contains(n)).collect();
+
+        let mut disagree_at: Option<usize> = None;
+        for (index, name) in reference_order.iter().enumerate() {
+            let ref_blue = reference.dag.get(name).unwrap().read().unwrap().is_blue;
+            let node_blue = node.dag.get(name).unwrap().read().unwrap().is_blue;
+
+            if ref_blue != node_blue {
+                report.colour_mismatches += 1;
+            }
+            if disagree_at.is_none() && (ref_blue != node_blue || &order[index] != name) {
+                disagree_at = Some(index);
+            }
+        }
+
+        if let Some(index) = disagree_at {
+            report.disagreeing_nodes.push(node.name.clone());
+            if first_divergent_index.is_none_or(|first| index < first) {
+                first_divergent_index = Some(index);
+            }
+        }
+
+        if sorted_keys_by_height(&node.tips, false) != reference_tips {
+            report.tips_mismatch.push(node.name.clone());
+        }
+    }
+
+    report.first_divergent = first_divergent_index.map(|index| reference_order[index].clone());
+    report.converged = report.first_divergent.is_none() && report.incomplete_nodes.is_empty() && report.tips_mismatch.is_empty();
+
+    if !report.converged {
+        debug!("check_convergence(): {}", report);
+    }
+
+    report
+}
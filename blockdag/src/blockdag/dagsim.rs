@@ -18,7 +18,7 @@ use std::collections::HashMap;
 use std::sync::{Arc,RwLock};
 
 use blockdag::Block;
-use blockdag::{sizeof_pastset,sorted_keys_by_height};
+use blockdag::{sizeof_pastset,sorted_keys_by_height,EMPTY_ROOT};
 
 pub fn dag_add_block(name: &str, references: &Vec<&str>, dag: &mut HashMap<String, Arc<RwLock<Block>>>){
 
@@ -33,6 +33,14 @@ pub fn dag_add_block(name: &str, references: &Vec<&str>, dag: &mut HashMap<Strin
         prev: HashMap::new(),
         next: HashMap::new(),
         tips_snapshot: HashMap::new(),
+        sequence: dag.len() as u64,
+        timestamp: 0,
+        target: 0,
+        nonce: 0,
+        merkle_root: EMPTY_ROOT,
+        miner: None,
+        signature: None,
+        txs: Vec::new(),
     }));
 
     //add references
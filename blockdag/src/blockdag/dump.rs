@@ -0,0 +1,256 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc,RwLock};
+use std::fmt;
+use std::error;
+use std::fs::File;
+use std::io;
+use std::io::{Read,Write};
+
+use serde_json;
+
+use blockdag::{Node,BlockRaw,Transaction,sorted_keys_by_height,check_merkle_root,check_signature};
+use blockdag::node::node_insert_raw;
+
+/// Full state of a node, as written into a JSON dump.
+///
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeDump{
+    pub name: String,
+    pub k: i32,
+    pub height: u64,
+    pub size_of_dag: u64,
+    pub tips: Vec<String>,
+    pub classmates: BTreeMap<u64, Vec<String>>,
+    pub hourglass: Vec<(u64,u64)>,
+    pub mined_blocks: u64,
+    pub blocks: Vec<BlockDump>,                             // in the arrival order of the local dag.
+}
+
+/// One block of the dump, with all its local computed fields.
+///
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BlockDump{
+    pub name: String,
+    pub height: u64,
+    pub size_of_past_set: u64,
+    pub size_of_past_blue: u64,
+    pub is_blue: bool,
+    pub size_of_anticone_blue: i32,
+    pub prev: Vec<String>,
+    #[serde(default)]
+    pub timestamp: u64,
+    #[serde(default)]
+    pub target: u64,
+    #[serde(default)]
+    pub nonce: u64,
+    #[serde(default)]
+    pub merkle_root: [u8; 32],
+    #[serde(default)]
+    pub miner: Option<[u8; 32]>,
+    #[serde(default)]
+    pub signature: Option<Vec<u8>>,
+    #[serde(default)]
+    pub txs: Vec<Transaction>,
+}
+
+#[derive(Debug)]
+pub enum DumpError{
+    Io(io::Error),
+    Json(serde_json::Error),
+    MissingParent{block: String, parent: String},
+    Rejected{block: String, reason: String},                // refused by the node, e.g. a duplicate or a bad header.
+    Mismatch{block: String, field: &'static str, stored: String, computed: String},
+}
+
+impl fmt::Display for DumpError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DumpError::Io(ref e) => write!(f, "io error: {}", e),
+            DumpError::Json(ref e) => write!(f, "json error: {}", e),
+            DumpError::MissingParent{ref block, ref parent} => write!(f, "block {} references {} which is not in the dump before it", block, parent),
+            DumpError::Rejected{ref block, ref reason} => write!(f, "block {} rejected: {}", block, reason),
+            DumpError::Mismatch{ref block, field, ref stored, ref computed} =>
+                write!(f, "block {}: stored {}={} but recomputed {}={}", block, field, stored, field, computed),
+        }
+    }
+}
+
+impl error::Error for DumpError {}
+
+impl From<io::Error> for DumpError {
+    fn from(e: io::Error) -> DumpError {
+        DumpError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for DumpError {
+    fn from(e: serde_json::Error) -> DumpError {
+        DumpError::Json(e)
+    }
+}
+
+/// Function providing a full dump of the node, 'k' is the k used by this node for the blue calculation.
+///
+pub fn node_dump(node: &Node, k: i32) -> NodeDump{
+
+    let mut blocks: Vec<(u64,BlockDump)> = Vec::with_capacity(node.dag.len());
+
+    for value in node.dag.values() {
+        let block = value.read().unwrap();
+        blocks.push((block.sequence, BlockDump{
+            name: block.name.clone(),
+            height: block.height,
+            size_of_past_set: block.size_of_past_set,
+            size_of_past_blue: block.size_of_past_blue,
+            is_blue: block.is_blue,
+            size_of_anticone_blue: block.size_of_anticone_blue,
+            prev: sorted_keys_by_height(&block.prev, false).into_iter().map(|(n,_)| n).collect(),
+            timestamp: block.timestamp,
+            target: block.target,
+            nonce: block.nonce,
+            merkle_root: block.merkle_root,
+            miner: block.miner,
+            signature: block.signature.clone(),
+            txs: block.txs.clone(),
+        }));
+    }
+    blocks.sort_by_key(|&(sequence,_)| sequence);
+
+    NodeDump{
+        name: node.name.clone(),
+        k,
+        height: node.height,
+        size_of_dag: node.size_of_dag,
+        tips: sorted_keys_by_height(&node.tips, false).into_iter().map(|(n,_)| n).collect(),
+        classmates: node.classmates.iter().map(|(h,c)| (*h, c.clone())).collect(),
+        hourglass: node.hourglass.clone(),
+        mined_blocks: node.mined_blocks,
+        blocks: blocks.into_iter().map(|(_,b)| b).collect(),
+    }
+}
+
+pub fn node_to_json(node: &Node, k: i32) -> String{
+    serde_json::to_string_pretty(&node_dump(node, k)).unwrap()
+}
+
+/// Rebuild a node from a dump, replaying the blocks in their original arrival order.
+///
+/// The colouring is recomputed by 'calc_blue' during the replay, and must match the stored one, otherwise a 'DumpError::Mismatch'
+/// on the first different block is returned. That's the way a failing simulation state is replayed.
+///
+pub fn node_from_dump(dump: &NodeDump) -> Result<Arc<RwLock<Node>>, DumpError>{
+
+    let node = Node::init(&dump.name);
+    {
+        let mut node_w = node.write().unwrap();
+
+        for block in &dump.blocks {
+            for prev in &block.prev {
+                if !node_w.dag.contains_key(prev) {
+                    return Err(DumpError::MissingParent{block: block.name.clone(), parent: prev.clone()});
+                }
+            }
+
+            let raw = BlockRaw{
+                name: block.name.clone(),
+                height: block.height,
+                size_of_past_set: block.size_of_past_set,
+                prev: block.prev.clone(),
+                timestamp: block.timestamp,
+                target: block.target,
+                nonce: block.nonce,
+                merkle_root: block.merkle_root,
+                miner: block.miner,
+                signature: block.signature.clone(),
+                txs: block.txs.clone(),
+            };
+            if node_w.dag.contains_key(&raw.name) {
+                return Err(DumpError::Rejected{block: raw.name.clone(), reason: String::from("already in the dump before it")});
+            }
+            if let Err(e) = check_merkle_root(&raw).and_then(|_| check_signature(&raw)) {
+                return Err(DumpError::Rejected{block: raw.name.clone(), reason: e.to_string()});
+            }
+            if let Err(e) = node_insert_raw(&raw, &mut node_w, dump.k) {
+                return Err(DumpError::Rejected{block: raw.name.clone(), reason: e.to_string()});
+            }
+        }
+
+        for stored in &dump.blocks {
+            let computed = node_w.dag.get(&stored.name).unwrap().read().unwrap();
+
+            macro_rules! check_field {
+                ( $field:ident ) => (
+                    if stored.$field != computed.$field {
+                        return Err(DumpError::Mismatch{block: stored.name.clone(), field: stringify!($field),
+                            stored: format!("{}", stored.$field), computed: format!("{}", computed.$field)});
+                    }
+                );
+            }
+            check_field!(height);
+            check_field!(size_of_past_set);
+            check_field!(is_blue);
+            check_field!(size_of_past_blue);
+            check_field!(size_of_anticone_blue);
+        }
+
+        let tips: Vec<String> = sorted_keys_by_height(&node_w.tips, false).into_iter().map(|(n,_)| n).collect();
+        if tips != dump.tips {
+            return Err(DumpError::Mismatch{block: String::from("tips"), field: "tips",
+                stored: format!("{:?}", dump.tips), computed: format!("{:?}", tips)});
+        }
+
+        // the node fields are recomputed by the replay too.
+        if node_w.height != dump.height {
+            return Err(DumpError::Mismatch{block: String::from("node"), field: "height",
+                stored: format!("{}", dump.height), computed: format!("{}", node_w.height)});
+        }
+        if node_w.size_of_dag != dump.size_of_dag {
+            return Err(DumpError::Mismatch{block: String::from("node"), field: "size_of_dag",
+                stored: format!("{}", dump.size_of_dag), computed: format!("{}", node_w.size_of_dag)});
+        }
+        let classmates: BTreeMap<u64, Vec<String>> = node_w.classmates.iter().map(|(h,c)| (*h, c.clone())).collect();
+        if classmates != dump.classmates {
+            return Err(DumpError::Mismatch{block: String::from("node"), field: "classmates",
+                stored: format!("{:?}", dump.classmates), computed: format!("{:?}", classmates)});
+        }
+
+        node_w.hourglass = dump.hourglass.clone();
+        node_w.mined_blocks = dump.mined_blocks;
+    }
+
+    Ok(node)
+}
+
+pub fn node_from_json(json: &str) -> Result<Arc<RwLock<Node>>, DumpError>{
+    let dump: NodeDump = serde_json::from_str(json)?;
+    node_from_dump(&dump)
+}
+
+pub fn node_save_json(node: &Node, k: i32, path: &str) -> Result<(), DumpError>{
+    let mut file = File::create(path)?;
+    file.write_all(node_to_json(node, k).as_bytes())?;
+    Ok(())
+}
+
+pub fn node_load_json(path: &str) -> Result<Arc<RwLock<Node>>, DumpError>{
+    let mut json = String::new();
+    File::open(path)?.read_to_string(&mut json)?;
+    node_from_json(&json)
+}
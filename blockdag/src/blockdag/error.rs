@@ -0,0 +1,91 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::error;
+use std::fmt;
+
+use blockdag::OutPoint;
+
+/// Why a block is rejected.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockError{
+    Duplicate(String),                                      // already in the dag.
+    NoParent(String),                                       // only Genesis has no reference.
+    MissingParent{block: String, parent: String},
+    DuplicateParent{block: String, parent: String},
+    Mismatch{block: String, field: &'static str, header: u64, computed: u64},
+    BadTarget{block: String, target: u64, computed: u64},   // too far from the locally computed target.
+    InsufficientWork(String),                               // the header hash exceeds the target.
+    BadMerkleRoot(String),                                  // the merkle root doesn't match the transactions.
+    BadSignature(String),                                   // missing, malformed or not matching the miner key.
+    TimestampTooOld{block: String, timestamp: u64, median: u64},
+    TimestampInFuture{block: String, timestamp: u64, clock: u64},
+}
+
+impl fmt::Display for BlockError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BlockError::Duplicate(ref block) => write!(f, "block {} is already in the dag", block),
+            BlockError::NoParent(ref block) => write!(f, "block {} has no reference", block),
+            BlockError::MissingParent{ref block, ref parent} => write!(f, "block {} references {} which is not in the dag", block, parent),
+            BlockError::DuplicateParent{ref block, ref parent} => write!(f, "block {} references {} twice", block, parent),
+            BlockError::Mismatch{ref block, field, header, computed} =>
+                write!(f, "block {}: header {}={} but computed {}={}", block, field, header, field, computed),
+            BlockError::BadTarget{ref block, target, computed} => write!(f, "block {}: target {} but computed {}", block, target, computed),
+            BlockError::InsufficientWork(ref block) => write!(f, "block {}: hash above the target", block),
+            BlockError::BadMerkleRoot(ref block) => write!(f, "block {}: merkle root doesn't match the transactions", block),
+            BlockError::BadSignature(ref block) => write!(f, "block {}: bad miner signature", block),
+            BlockError::TimestampTooOld{ref block, timestamp, median} =>
+                write!(f, "block {}: timestamp {} not after the median time past {}", block, timestamp, median),
+            BlockError::TimestampInFuture{ref block, timestamp, clock} =>
+                write!(f, "block {}: timestamp {} too far ahead of the clock {}", block, timestamp, clock),
+        }
+    }
+}
+
+impl error::Error for BlockError {}
+
+/// Why a transaction is rejected by the ledger.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum TxError{
+    Duplicate(String),                                      // already accepted, in a block ordered before.
+    DuplicateInput{tx: String, input: OutPoint},
+    DoubleSpend{tx: String, input: OutPoint, spent_by: String},
+    MissingInput{tx: String, input: OutPoint},              // no such output, or not yet in the order.
+    Overspend{tx: String, inputs: u64, outputs: u64},
+    Overflow(String),                                       // the sum of the inputs or of the outputs doesn't fit in u64.
+    MempoolFull(String),
+}
+
+impl fmt::Display for TxError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TxError::Duplicate(ref tx) => write!(f, "transaction {} is already accepted", tx),
+            TxError::DuplicateInput{ref tx, ref input} => write!(f, "transaction {} spends {} twice", tx, input),
+            TxError::DoubleSpend{ref tx, ref input, ref spent_by} => write!(f, "transaction {} spends {} already spent by {}", tx, input, spent_by),
+            TxError::MissingInput{ref tx, ref input} => write!(f, "transaction {} spends {} which is unknown", tx, input),
+            TxError::Overspend{ref tx, inputs, outputs} => write!(f, "transaction {} spends {} but outputs {}", tx, inputs, outputs),
+            TxError::Overflow(ref tx) => write!(f, "transaction {} amounts overflow", tx),
+            TxError::MempoolFull(ref tx) => write!(f, "transaction {} dropped, the mempool is full", tx),
+        }
+    }
+}
+
+impl error::Error for TxError {}
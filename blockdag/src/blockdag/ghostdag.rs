@@ -0,0 +1,296 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet,VecDeque};
+use std::error;
+use std::fmt;
+
+use blockdag::{Node,calc_blue,node_add_block};
+use blockdag::node::colour_new_block;
+
+#[derive(Debug, PartialEq)]
+pub enum ColouringError{
+    Duplicate(String),                                      // already in the dag.
+    MissingData(String),                                    // not coloured by this engine, e.g. in a dag loaded from a dump.
+}
+
+impl fmt::Display for ColouringError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ColouringError::Duplicate(ref block) => write!(f, "block {} already in the dag", block),
+            ColouringError::MissingData(ref block) => write!(f, "block {} not coloured by this engine", block),
+        }
+    }
+}
+
+impl error::Error for ColouringError {}
+
+/// A blue colouring algorithm. The block is already in the dag, with its references, and the tips are updated:
+/// see 'colour_new_block'.
+///
+pub trait ColouringEngine{
+    fn name(&self) -> &'static str;
+    fn add_block(&mut self, name: &str, node: &mut Node, k: i32) -> Result<(), ColouringError>;
+    /// The blue blocks of the dag, as seen from the current tips.
+    fn blue_set(&self, node: &Node, k: i32) -> Result<HashSet<String>, ColouringError>;
+}
+
+/// Function providing a block addition coloured by the engine instead of 'calc_blue'. The tips, the virtual block and
+/// the selected chain are updated as by 'node_add_block'.
+///
+pub fn node_add_block_with(engine: &mut dyn ColouringEngine, name: &str, references: &Vec<&str>, node: &mut Node, k: i32) -> Result<(), ColouringError>{
+
+    if !node_add_block(name, references, node, k, false) {
+        return Err(ColouringError::Duplicate(name.to_string()));
+    }
+    colour_new_block(name, node, k, engine)
+}
+
+/// The original algorithm: 'calc_blue' colours the anticone of the tips, the colours are stored in the blocks.
+///
+pub struct CalcBlue;
+
+impl ColouringEngine for CalcBlue {
+
+    fn name(&self) -> &'static str{
+        "calc_blue"
+    }
+
+    fn add_block(&mut self, name: &str, node: &mut Node, k: i32) -> Result<(), ColouringError>{
+        calc_blue(name, node, k);
+        Ok(())
+    }
+
+    fn blue_set(&self, node: &Node, _k: i32) -> Result<HashSet<String>, ColouringError>{
+        Ok(node.dag.iter().filter(|(_, block)| block.read().unwrap().is_blue).map(|(name,_)| name.clone()).collect())
+    }
+}
+
+/// The GHOSTDAG data of a block, immutable once the block is added.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GhostdagData{
+    pub selected_parent: Option<String>,                    // the parent with the best blue score, then the smallest name.
+    pub blue_score: u64,                                    // blue blocks of its past.
+    pub mergeset_blues: Vec<String>,                        // the selected parent firstly, then in mergeset order.
+    pub mergeset_reds: Vec<String>,
+    pub blues_anticone_sizes: HashMap<String, u64>,         // blue anticone size of its blue mergeset blocks, in its blue set.
+}
+
+/// GHOSTDAG: the blue set of a block is the one of its selected parent, plus the blocks of its mergeset (its past not
+/// in the past of the selected parent) which keep every blue anticone within K, taken in (blue score, name) order.
+/// Unlike 'calc_blue', nothing is recoloured: the blue set of the dag is the one of a virtual block over the tips.
+///
+#[derive(Default)]
+pub struct Ghostdag{
+    pub data: HashMap<String, GhostdagData>,
+}
+
+impl Ghostdag {
+
+    pub fn new() -> Ghostdag{
+        Ghostdag::default()
+    }
+
+    /// The GHOSTDAG data of a block coloured by this engine.
+    ///
+    pub fn get(&self, name: &str) -> Result<&GhostdagData, ColouringError>{
+        self.data.get(name).ok_or_else(|| ColouringError::MissingData(name.to_string()))
+    }
+
+    /// The GHOSTDAG data of a block with these parents. All the blocks of their past must have been coloured by this engine.
+    ///
+    pub fn compute(&self, parents: &[String], node: &Node, k: i32) -> Result<GhostdagData, ColouringError>{
+
+        let mut scores: Vec<(u64, &String)> = Vec::with_capacity(parents.len());
+        for name in parents {
+            scores.push((self.get(name)?.blue_score, name));
+        }
+        let selected_parent = match scores.into_iter().max_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(a.1))) {
+            Some((_, name)) => name.clone(),
+            None => return Ok(GhostdagData::default()),         // Genesis.
+        };
+
+        let mut new_data = GhostdagData{
+            selected_parent: Some(selected_parent.clone()),
+            blue_score: 0,
+            mergeset_blues: vec![selected_parent.clone()],
+            mergeset_reds: Vec::new(),
+            blues_anticone_sizes: HashMap::new(),
+        };
+        new_data.blues_anticone_sizes.insert(selected_parent.clone(), 0);
+
+        for candidate in self.mergeset(&selected_parent, parents, node)? {
+            match self.check_blue_candidate(&new_data, &candidate, node, k as u64)? {
+                Some(anticone_sizes) => {
+                    new_data.blues_anticone_sizes.insert(candidate.clone(), anticone_sizes.len() as u64);
+                    for (blue, size) in anticone_sizes {
+                        new_data.blues_anticone_sizes.insert(blue, size + 1);
+                    }
+                    new_data.mergeset_blues.push(candidate);
+                },
+                None => new_data.mergeset_reds.push(candidate),
+            }
+        }
+
+        new_data.blue_score = self.get(&selected_parent)?.blue_score + new_data.mergeset_blues.len() as u64;
+        Ok(new_data)
+    }
+
+    /// The past of the parents not in the past of the selected parent, in (blue score, name) order.
+    ///
+    fn mergeset(&self, selected_parent: &str, parents: &[String], node: &Node) -> Result<Vec<String>, ColouringError>{
+
+        let mut mergeset: HashSet<String> = HashSet::new();
+        let mut queue: VecDeque<String> = parents.iter().filter(|name| *name != selected_parent).cloned().collect();
+        while let Some(name) = queue.pop_front() {
+            if mergeset.contains(&name) || name == selected_parent || is_in_past(node, &name, selected_parent) {
+                continue;
+            }
+            for prev in node.dag[&name].read().unwrap().prev.keys() {
+                queue.push_back(prev.clone());
+            }
+            mergeset.insert(name);
+        }
+
+        let mut sorted: Vec<(u64, String)> = Vec::with_capacity(mergeset.len());
+        for name in mergeset {
+            sorted.push((self.get(&name)?.blue_score, name));
+        }
+        sorted.sort();
+        Ok(sorted.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// The blue anticone sizes of the blues in the anticone of the candidate, if it can be blue. Its blue anticone must
+    /// stay within K, and so must the ones of those blues. The blues are walked down the selected parent chain, until
+    /// a chain block in the past of the candidate.
+    ///
+    fn check_blue_candidate(&self, new_data: &GhostdagData, candidate: &str, node: &Node, k: u64) -> Result<Option<HashMap<String, u64>>, ColouringError>{
+
+        if new_data.mergeset_blues.len() as u64 == k + 1 {
+            return Ok(None);
+        }
+
+        let mut anticone_sizes: HashMap<String, u64> = HashMap::new();
+        let mut chain_data = new_data;
+        let mut chain_block: Option<&str> = None;                  // None for the new block.
+        loop {
+            if let Some(name) = chain_block {
+                if is_in_past(node, name, candidate) {
+                    break;
+                }
+            }
+            for blue in &chain_data.mergeset_blues {
+                if is_in_past(node, blue, candidate) {
+                    continue;
+                }
+                let size = self.blue_anticone_size(blue, new_data)?;
+                if size == k {
+                    return Ok(None);
+                }
+                anticone_sizes.insert(blue.clone(), size);
+                if anticone_sizes.len() as u64 > k {
+                    return Ok(None);
+                }
+            }
+            match chain_data.selected_parent {
+                Some(ref selected_parent) => {
+                    chain_block = Some(selected_parent);
+                    chain_data = self.get(selected_parent)?;
+                },
+                None => break,
+            }
+        }
+
+        Ok(Some(anticone_sizes))
+    }
+
+    /// The blue anticone size of a blue block, in the blue set of the new block: the one recorded by the first block
+    /// of the chain which coloured or updated it.
+    ///
+    fn blue_anticone_size(&self, blue: &str, new_data: &GhostdagData) -> Result<u64, ColouringError>{
+
+        let mut data = new_data;
+        loop {
+            if let Some(size) = data.blues_anticone_sizes.get(blue) {
+                return Ok(*size);
+            }
+            match data.selected_parent {
+                Some(ref selected_parent) => data = self.get(selected_parent)?,
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+impl ColouringEngine for Ghostdag {
+
+    fn name(&self) -> &'static str{
+        "ghostdag"
+    }
+
+    fn add_block(&mut self, name: &str, node: &mut Node, k: i32) -> Result<(), ColouringError>{
+        let parents: Vec<String> = node.dag[name].read().unwrap().prev.keys().cloned().collect();
+        let data = self.compute(&parents, node, k)?;
+        self.data.insert(name.to_string(), data);
+        Ok(())
+    }
+
+    fn blue_set(&self, node: &Node, k: i32) -> Result<HashSet<String>, ColouringError>{
+
+        let tips: Vec<String> = node.tips.keys().cloned().collect();
+        let mut blues: HashSet<String> = HashSet::new();
+        let mut data = self.compute(&tips, node, k)?;
+        loop {
+            blues.extend(data.mergeset_blues.iter().cloned());
+            match data.selected_parent {
+                Some(ref selected_parent) => data = self.get(selected_parent)?.clone(),
+                None => break,
+            }
+        }
+        for name in node.dag.keys() {
+            if self.get(name)?.selected_parent.is_none() {
+                blues.insert(name.clone());
+            }
+        }
+        Ok(blues)
+    }
+}
+
+/// Whether 'ancestor' is in the past of 'block'. The walk skips the blocks whose past isn't larger than the one of
+/// the ancestor, which can't reach it.
+///
+fn is_in_past(node: &Node, ancestor: &str, block: &str) -> bool{
+
+    let floor = node.dag[ancestor].read().unwrap().size_of_past_set;
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut queue: VecDeque<String> = node.dag[block].read().unwrap().prev.keys().cloned().collect();
+    while let Some(name) = queue.pop_front() {
+        if name == ancestor {
+            return true;
+        }
+        if !visited.insert(name.clone()) {
+            continue;
+        }
+        let prev = node.dag[&name].read().unwrap();
+        if prev.size_of_past_set <= floor {
+            continue;
+        }
+        queue.extend(prev.prev.keys().cloned());
+    }
+    false
+}
@@ -0,0 +1,126 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::cmp::Reverse;
+use std::collections::{HashMap,BTreeSet};
+
+use blockdag::{Node,BlockRaw,Message,BlockStash,SyncResult,handle_message,MAX_HEADERS};
+
+/// Initial block download from one peer: ask the peer for its tips, then walk back by batches of headers until
+/// all of them connect to the local dag, then add them in the arrival order of the peer, i.e. the reverse of the headers order.
+///
+/// 'GetTips' --> 'Tips' --> 'GetHeaders' --> 'Headers' --> 'GetHeaders' ... --> 'Headers' (connected).
+///
+pub struct DagSync{
+    pub batch: usize,                                       // headers per 'GetHeaders' request, up to 'MAX_HEADERS'.
+    pub requests: u64,
+    pub headers_received: u64,
+    pending: HashMap<String, (u64, BlockRaw)>,              // received headers, not connected yet, with their receiving order.
+    frontier: BTreeSet<String>,                             // parents of the pending headers, neither in the dag nor pending.
+    done: bool,
+}
+
+impl DagSync {
+
+    pub fn new(batch: usize) -> DagSync{
+        DagSync{
+            batch: batch.clamp(1, MAX_HEADERS),
+            requests: 0,
+            headers_received: 0,
+            pending: HashMap::new(),
+            frontier: BTreeSet::new(),
+            done: false,
+        }
+    }
+
+    /// The first request to send to the peer.
+    ///
+    pub fn start(&mut self) -> Message{
+        self.done = false;
+        self.requests += 1;
+        Message::GetTips
+    }
+
+    pub fn is_done(&self) -> bool{
+        self.done
+    }
+
+    pub fn pending(&self) -> usize{
+        self.pending.len()
+    }
+
+    /// Handle a reply of the peer. Return the blocks added into the dag, when the headers connect, and the next request.
+    /// The connected blocks go through the stash, so that the blocks received meanwhile and waiting for them are released too.
+    ///
+    pub fn handle_reply(&mut self, message: Message, node: &mut Node, stash: &mut BlockStash, k: i32, clock: u64) -> SyncResult{
+
+        let mut result = SyncResult::default();
+
+        match message {
+            Message::Tips(tips) => {
+                for tip in tips {
+                    if !node.dag.contains_key(&tip) {
+                        self.frontier.insert(tip);
+                    }
+                }
+            },
+            Message::Headers(headers) => {
+                if headers.is_empty() && !self.frontier.is_empty() {
+                    warn!("DagSync: node={}. peer has none of {} requested blocks, sync aborted.", node.name, self.frontier.len());
+                    self.frontier.clear();
+                    self.pending.clear();
+                    self.done = true;
+                    return result;
+                }
+
+                for header in headers {
+                    self.headers_received += 1;
+                    if node.dag.contains_key(&header.name) || self.pending.contains_key(&header.name) {
+                        continue;
+                    }
+                    self.frontier.remove(&header.name);
+                    for prev in &header.prev {
+                        if !node.dag.contains_key(prev) && !self.pending.contains_key(prev) {
+                            self.frontier.insert(prev.clone());
+                        }
+                    }
+                    self.pending.insert(header.name.clone(), (self.headers_received, header));
+                }
+            },
+            _ => return result,
+        }
+
+        if !self.frontier.is_empty() {
+            self.requests += 1;
+            result.replies.push(Message::GetHeaders{from: self.frontier.iter().cloned().collect(), max: self.batch});
+            return result;
+        }
+
+        // connected: add the pending blocks from the last received one. a block received before its parents, from a peer not
+        // following the 'Headers' order, waits in the stash.
+        let mut connected: Vec<(u64, BlockRaw)> = self.pending.drain().map(|(_, pending)| pending).collect();
+        connected.sort_by_key(|&(order, _)| Reverse(order));
+
+        for (_, header) in connected {
+            let mut added = handle_message(Message::Block(header), node, stash, k, clock).added;
+            result.added.append(&mut added);
+        }
+
+        info!("DagSync: node={}. synced, {} blocks added with {} requests. size_of_dag={}", node.name, result.added.len(), self.requests, node.size_of_dag);
+        self.done = true;
+        result
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet,VecDeque};
+
+use blockdag::{Node,Message,BlockStash};
+
+pub const MAX_KNOWN_BLOCKS: usize = 50_000;                 // default blocks remembered per peer, see 'PeerInventory'.
+
+/// How a node relays the blocks it accepts to its peers.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum Relay{
+    Push,                                                   // the full block, to all the peers but the one it came from.
+    Inventory,                                              // 'Inv' announce, the peer requests the blocks it misses with 'GetData'.
+}
+
+/// Blocks one peer is known to have: announced by it, received from it, or sent or announced to it.
+///
+/// At most 'max_known' blocks are remembered, the oldest ones are forgotten firstly: at worst, such a block is announced
+/// again to the peer, which ignores it.
+///
+#[derive(Clone, Debug)]
+pub struct PeerInventory{
+    pub max_known: usize,
+    known: HashSet<String>,
+    order: VecDeque<String>,                                // the known blocks, the oldest firstly.
+}
+
+impl Default for PeerInventory {
+
+    fn default() -> PeerInventory {
+        PeerInventory::new(MAX_KNOWN_BLOCKS)
+    }
+}
+
+impl PeerInventory {
+
+    pub fn new(max_known: usize) -> PeerInventory{
+        PeerInventory{
+            max_known,
+            known: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize{
+        self.known.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.known.is_empty()
+    }
+
+    pub fn mark_known(&mut self, name: &str){
+        self.insert(name);
+    }
+
+    pub fn knows(&self, name: &str) -> bool{
+        self.known.contains(name)
+    }
+
+    /// Keep the blocks to announce to this peer, the ones it doesn't know yet, and mark them known.
+    ///
+    pub fn announce(&mut self, names: &[String]) -> Vec<String>{
+        names.iter().filter(|name| self.insert(name)).cloned().collect()
+    }
+
+    /// Mark a block known. Return false if it was already known.
+    ///
+    fn insert(&mut self, name: &str) -> bool{
+
+        if self.known.contains(name) {
+            return false;
+        }
+        self.known.insert(name.to_string());
+        self.order.push_back(name.to_string());
+        while self.order.len() > self.max_known {
+            if let Some(oldest) = self.order.pop_front() {
+                self.known.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// Function providing the 'GetData' answer of an 'Inv': the announced blocks neither in the dag nor in the stash,
+/// not rejected, and not requested from another peer less than 'request_timeout' (of the stash config) ago.
+/// 'in_flight' is the request time of the requested blocks not received yet.
+///
+pub fn handle_inv(names: &[String], node: &Node, stash: &BlockStash, in_flight: &mut HashMap<String,u64>, clock: u64) -> Option<Message>{
+
+    let wanted: Vec<String> = names.iter()
+        .filter(|name| !node.dag.contains_key(*name) && !stash.contains(name) && !stash.is_rejected(name))
+        .filter(|name| request_due(name, in_flight, stash.config.request_timeout, clock))
+        .cloned().collect();
+
+    if wanted.is_empty() {
+        None
+    }else{
+        Some(Message::GetData(wanted))
+    }
+}
+
+/// Whether a block is to be requested now, i.e. not in flight or in flight for too long. If so, it's put in flight.
+///
+pub fn request_due(name: &str, in_flight: &mut HashMap<String,u64>, timeout: u64, clock: u64) -> bool{
+
+    let due = in_flight.get(name).is_none_or(|&time| clock.saturating_sub(time) >= timeout);
+    if due {
+        in_flight.insert(name.to_string(), clock);
+    }
+    due
+}
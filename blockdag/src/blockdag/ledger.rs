@@ -0,0 +1,280 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet};
+use std::fmt;
+use sha2::{Sha256,Digest};
+
+use blockdag::{Node,TxError};
+use blockdag::ordering::dag_order_after;
+
+/// An output of a transaction.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct OutPoint{
+    pub tx: String,
+    pub index: u32,
+}
+
+impl fmt::Display for OutPoint {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.tx, self.index)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TxOut{
+    pub owner: String,
+    pub amount: u64,
+}
+
+/// A simple transaction: it spends whole outputs of previous transactions. Without input, it creates coins (coinbase).
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Transaction{
+    pub id: String,                                         // in simulation, a readable string stands for the hash.
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<TxOut>,
+}
+
+impl Transaction {
+
+    pub fn is_coinbase(&self) -> bool{
+        self.inputs.is_empty()
+    }
+
+    /// The sum of the outputs, none if it overflows.
+    ///
+    pub fn amount_out(&self) -> Option<u64>{
+        self.outputs.iter().try_fold(0u64, |sum, output| sum.checked_add(output.amount))
+    }
+
+    pub fn outpoint(&self, index: u32) -> OutPoint{
+        OutPoint{tx: self.id.clone(), index}
+    }
+
+    /// The sha256 of all the fields.
+    ///
+    pub fn hash(&self) -> [u8; 32]{
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.id.as_bytes());
+        for input in &self.inputs {
+            hasher.update([0u8]);
+            hasher.update(input.tx.as_bytes());
+            hasher.update(input.index.to_be_bytes());
+        }
+        for output in &self.outputs {
+            hasher.update([1u8]);
+            hasher.update(output.owner.as_bytes());
+            hasher.update(output.amount.to_be_bytes());
+        }
+        hasher.finalize().into()
+    }
+}
+
+/// What's needed to roll back an applied block.
+///
+#[derive(Clone, Debug, Default)]
+struct BlockUndo{
+    spent: Vec<(OutPoint, TxOut)>,                          // the outputs spent by the block, to restore.
+    created: Vec<OutPoint>,                                 // the outputs created by the block, to remove.
+    accepted: Vec<String>,
+    rejected: usize,                                        // how many rejections the block added.
+}
+
+/// Blocks rolled back and applied by a ledger update.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LedgerUpdate{
+    pub rolled_back: usize,
+    pub applied: usize,
+}
+
+/// Unspent outputs, from the transactions of the blocks applied in the dag order (see 'dag_order').
+///
+/// All the blocks are applied, red ones included. A transaction conflicting with one applied before it is rejected,
+/// so a double spend is resolved by the order: the first spend in the order wins, whatever the arrival order.
+///
+/// When a new block changes the order, i.e. it's ordered before some applied blocks or 'calc_blue' recolours blocks,
+/// 'update' rolls back the applied blocks after the common ordered prefix with their undo data, and applies the new order.
+/// It follows the changes through 'Node::colour_log', so only the order after the first changed block is computed again.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Ledger{
+    pub utxos: HashMap<OutPoint, TxOut>,
+    pub spent: HashMap<OutPoint, String>,                   // spent output -> the accepted transaction spending it.
+    pub accepted: HashMap<String, String>,                  // accepted transaction -> the block accepting it.
+    pub rejected: Vec<(String, TxError)>,                   // (block, why one of its transactions is rejected), in the applying order.
+    pub blocks: Vec<String>,                                // the applied blocks, in order.
+    undo: Vec<BlockUndo>,                                   // one per applied block.
+    positions: HashMap<String, usize>,                      // applied block -> its index in 'blocks'.
+    position: usize,                                        // in the colour log of the node.
+}
+
+impl Ledger {
+
+    pub fn new() -> Ledger{
+        Ledger::default()
+    }
+
+    /// Whether the transaction can be applied on the current state.
+    ///
+    pub fn check_tx(&self, tx: &Transaction) -> Result<(), TxError>{
+
+        if self.accepted.contains_key(&tx.id) {
+            return Err(TxError::Duplicate(tx.id.clone()));
+        }
+
+        let mut inputs: HashSet<&OutPoint> = HashSet::new();
+        let mut amount_in: u64 = 0;
+        for input in &tx.inputs {
+            if !inputs.insert(input) {
+                return Err(TxError::DuplicateInput{tx: tx.id.clone(), input: input.clone()});
+            }
+            if let Some(spent_by) = self.spent.get(input) {
+                return Err(TxError::DoubleSpend{tx: tx.id.clone(), input: input.clone(), spent_by: spent_by.clone()});
+            }
+            match self.utxos.get(input) {
+                Some(output) => amount_in = amount_in.checked_add(output.amount).ok_or_else(|| TxError::Overflow(tx.id.clone()))?,
+                None => return Err(TxError::MissingInput{tx: tx.id.clone(), input: input.clone()}),
+            }
+        }
+
+        let amount_out = tx.amount_out().ok_or_else(|| TxError::Overflow(tx.id.clone()))?;
+        if !tx.is_coinbase() && amount_out > amount_in {
+            return Err(TxError::Overspend{tx: tx.id.clone(), inputs: amount_in, outputs: amount_out});
+        }
+
+        Ok(())
+    }
+
+    /// Apply the transactions of the next block in the order. Return the rejected ones.
+    ///
+    pub fn apply_block(&mut self, block: &str, txs: &[Transaction]) -> Vec<TxError>{
+
+        let mut rejected: Vec<TxError> = Vec::new();
+        let mut undo = BlockUndo::default();
+
+        for tx in txs {
+            if let Err(e) = self.check_tx(tx) {
+                debug!("ledger: block {}. transaction rejected: {}", block, e);
+                self.rejected.push((block.to_string(), e.clone()));
+                rejected.push(e);
+                continue;
+            }
+
+            for input in &tx.inputs {
+                let output = self.utxos.remove(input).unwrap();
+                self.spent.insert(input.clone(), tx.id.clone());
+                undo.spent.push((input.clone(), output));
+            }
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let outpoint = tx.outpoint(index as u32);
+                self.utxos.insert(outpoint.clone(), output.clone());
+                undo.created.push(outpoint);
+            }
+            self.accepted.insert(tx.id.clone(), block.to_string());
+            undo.accepted.push(tx.id.clone());
+        }
+
+        undo.rejected = rejected.len();
+        self.positions.insert(block.to_string(), self.blocks.len());
+        self.blocks.push(block.to_string());
+        self.undo.push(undo);
+        rejected
+    }
+
+    /// Roll back the last applied block. Return its name, or None if no block is applied.
+    ///
+    pub fn rollback(&mut self) -> Option<String>{
+
+        let block = self.blocks.pop()?;
+        let undo = self.undo.pop().unwrap();
+        self.positions.remove(&block);
+
+        // in the reverse order of 'apply_block': an output may be created and spent in the same block.
+        for (outpoint, output) in undo.spent {
+            self.spent.remove(&outpoint);
+            self.utxos.insert(outpoint, output);
+        }
+        for outpoint in &undo.created {
+            self.utxos.remove(outpoint);
+        }
+        for tx in &undo.accepted {
+            self.accepted.remove(tx);
+        }
+        let rejected = self.rejected.len() - undo.rejected;
+        self.rejected.truncate(rejected);
+
+        Some(block)
+    }
+
+    /// Bring the ledger to the current order of the node: roll back to the common ordered prefix, then apply the new order.
+    ///
+    /// The order is kept up to the last applied blue block ordered before the first block added or recoloured since the
+    /// last update (see 'dag_order_after'). The order after it is computed again, and compared with the applied blocks.
+    ///
+    pub fn update(&mut self, node: &Node) -> LedgerUpdate{
+
+        // without the log since the last update, the whole order is computed again.
+        let log = node.colour_log_since(self.position);
+        let changed = log.map(|log| log.iter().map(|name| (node.dag.get(name).unwrap().read().unwrap().size_of_past_set, name)).min());
+
+        let kept = match changed {
+            Some(changed) => self.blocks.iter().rposition(|name| node.dag.get(name).is_some_and(|block| {
+                let block = block.read().unwrap();
+                block.is_blue && changed.is_none_or(|first| (block.size_of_past_set, name) < first)
+            })).map_or(0, |index| index + 1),
+            None => 0,
+        };
+        self.position = node.colour_log_end();
+        let order = dag_order_after(&node.dag, |name| self.positions.get(name).is_some_and(|&index| index < kept));
+        let common = kept + self.blocks[kept..].iter().zip(order.iter()).take_while(|(applied, ordered)| applied == ordered).count();
+
+        let mut update = LedgerUpdate::default();
+        while self.blocks.len() > common {
+            self.rollback();
+            update.rolled_back += 1;
+        }
+        for name in &order[common - kept..] {
+            let block = node.dag.get(name).unwrap().read().unwrap();
+            self.apply_block(name, &block.txs);
+            update.applied += 1;
+        }
+
+        if update.rolled_back > 0 {
+            debug!("ledger: node={}. reorg, {} blocks rolled back, {} applied", node.name, update.rolled_back, update.applied);
+        }
+        update
+    }
+
+    /// The balance of an owner: the sum of its unspent outputs, saturated at u64::MAX since coinbases are not bounded.
+    ///
+    pub fn balance(&self, owner: &str) -> u64{
+        self.utxos.values().filter(|output| output.owner == owner).fold(0, |sum, output| sum.saturating_add(output.amount))
+    }
+}
+
+/// Function providing the ledger of a node: all its blocks applied from Genesis, in the dag order.
+///
+pub fn ledger_from_node(node: &Node) -> Ledger{
+
+    let mut ledger = Ledger::new();
+    ledger.update(node);
+    ledger
+}
@@ -0,0 +1,205 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet};
+
+use sha2::{Sha256,Digest};
+
+use blockdag::{Node,Ledger,OutPoint,Transaction,TxError,BlockTemplate};
+
+/// Limits and sharding of the mempool.
+///
+/// Blocks mined in parallel share most of their mempool, so they would include the same transactions, and all but the
+/// first one in the order would be wasted. The transactions are split in 'shards' by hash: a miner fills its block
+/// from its own shard, given by the hash of its name, then from the other shards if there's room left. So miners
+/// working at the same time, which don't know each other's blocks yet, mostly pick different transactions.
+///
+#[derive(Clone, Debug)]
+pub struct MempoolConfig{
+    pub max_txs: usize,
+    pub max_block_txs: usize,
+    pub shards: usize,
+}
+
+impl Default for MempoolConfig {
+
+    fn default() -> MempoolConfig {
+        MempoolConfig{
+            max_txs: 10_000,
+            max_block_txs: 100,
+            shards: 4,
+        }
+    }
+}
+
+/// Transactions waiting for a block, valid on the virtual state: the ledger of the node, plus the transactions
+/// of the mempool added before them.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Mempool{
+    pub config: MempoolConfig,
+    txs: HashMap<String, Transaction>,
+    order: Vec<String>,                                     // arrival order, a transaction comes after the ones it spends.
+    spends: HashMap<OutPoint, String>,                      // output -> the mempool transaction spending it.
+}
+
+impl Mempool {
+
+    pub fn new(config: MempoolConfig) -> Mempool{
+        Mempool{config, ..Mempool::default()}
+    }
+
+    pub fn len(&self) -> usize{
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.order.is_empty()
+    }
+
+    pub fn contains(&self, tx: &str) -> bool{
+        self.txs.contains_key(tx)
+    }
+
+    /// Whether the transaction is valid on the virtual state.
+    ///
+    pub fn check_tx(&self, tx: &Transaction, ledger: &Ledger) -> Result<(), TxError>{
+
+        if self.txs.contains_key(&tx.id) || ledger.accepted.contains_key(&tx.id) {
+            return Err(TxError::Duplicate(tx.id.clone()));
+        }
+
+        let mut inputs = HashSet::new();
+        let mut amount_in: u64 = 0;
+        for input in &tx.inputs {
+            if !inputs.insert(input) {
+                return Err(TxError::DuplicateInput{tx: tx.id.clone(), input: input.clone()});
+            }
+            if let Some(spent_by) = ledger.spent.get(input).or_else(|| self.spends.get(input)) {
+                return Err(TxError::DoubleSpend{tx: tx.id.clone(), input: input.clone(), spent_by: spent_by.clone()});
+            }
+            let output = ledger.utxos.get(input).or_else(|| {
+                self.txs.get(&input.tx).and_then(|parent| parent.outputs.get(input.index as usize))
+            });
+            match output {
+                Some(output) => amount_in = amount_in.checked_add(output.amount).ok_or_else(|| TxError::Overflow(tx.id.clone()))?,
+                None => return Err(TxError::MissingInput{tx: tx.id.clone(), input: input.clone()}),
+            }
+        }
+
+        let amount_out = tx.amount_out().ok_or_else(|| TxError::Overflow(tx.id.clone()))?;
+        if !tx.is_coinbase() && amount_out > amount_in {
+            return Err(TxError::Overspend{tx: tx.id.clone(), inputs: amount_in, outputs: amount_out});
+        }
+
+        Ok(())
+    }
+
+    /// Add a transaction valid on the virtual state.
+    ///
+    pub fn add(&mut self, tx: Transaction, ledger: &Ledger) -> Result<(), TxError>{
+
+        self.check_tx(&tx, ledger)?;
+        if self.order.len() >= self.config.max_txs {
+            return Err(TxError::MempoolFull(tx.id));
+        }
+
+        for input in &tx.inputs {
+            self.spends.insert(input.clone(), tx.id.clone());
+        }
+        self.order.push(tx.id.clone());
+        self.txs.insert(tx.id.clone(), tx);
+        Ok(())
+    }
+
+    /// Check again all the transactions after a ledger update, in their arrival order. Remove the ones accepted
+    /// by a block, and the ones no longer valid, i.e. conflicting with a block or spending a removed transaction.
+    /// Return the removed transactions.
+    ///
+    pub fn update(&mut self, ledger: &Ledger) -> Vec<String>{
+
+        let mut txs = ::std::mem::take(&mut self.txs);
+        let order = ::std::mem::take(&mut self.order);
+        self.spends.clear();
+
+        let mut removed: Vec<String> = Vec::new();
+        for id in order {
+            let tx = txs.remove(&id).unwrap();
+            if let Err(e) = self.add(tx, ledger) {
+                trace!("mempool: transaction {} removed: {}", id, e);
+                removed.push(id);
+            }
+        }
+        removed
+    }
+
+    /// Function providing the shard of a transaction, from its hash.
+    ///
+    pub fn shard(&self, tx: &Transaction) -> usize{
+        self.shard_of(&tx.hash())
+    }
+
+    /// Function providing the shard of a miner, from the hash of its name.
+    ///
+    pub fn miner_shard(&self, miner: &str) -> usize{
+        self.shard_of(&Sha256::digest(miner.as_bytes()).into())
+    }
+
+    fn shard_of(&self, hash: &[u8; 32]) -> usize{
+        let mut bytes = [0u8; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        (u64::from_be_bytes(bytes) % self.config.shards.max(1) as u64) as usize
+    }
+
+    /// Select at most 'max_block_txs' transactions for a new block mined by this node: the ones of its shard firstly,
+    /// see 'MempoolConfig'. A transaction spending another mempool transaction is only selected after it, so the
+    /// transactions waiting for a parent are checked again once it's selected, whatever its shard.
+    ///
+    pub fn select(&self, node: &Node) -> Vec<Transaction>{
+
+        let shard = self.miner_shard(&node.name);
+        let (own, others): (Vec<&String>, Vec<&String>) = self.order.iter().partition(|id| self.shard(&self.txs[*id]) == shard);
+
+        let mut selected: Vec<Transaction> = Vec::new();
+        let mut selected_ids: HashSet<&str> = HashSet::new();
+        loop {
+            let selected_before = selected.len();
+            for id in own.iter().chain(others.iter()) {
+                if selected.len() >= self.config.max_block_txs {
+                    return selected;
+                }
+                let tx = &self.txs[*id];
+                if selected_ids.contains(id.as_str()) {
+                    continue;
+                }
+                let ready = tx.inputs.iter().all(|input| !self.txs.contains_key(&input.tx) || selected_ids.contains(input.tx.as_str()));
+                if ready {
+                    selected_ids.insert(id);
+                    selected.push(tx.clone());
+                }
+            }
+            if selected.len() == selected_before {
+                return selected;
+            }
+        }
+    }
+
+    /// Fill the payload of a block template, see 'select'.
+    ///
+    pub fn fill_template(&self, template: &mut BlockTemplate, node: &Node){
+        template.txs = self.select(node);
+    }
+}
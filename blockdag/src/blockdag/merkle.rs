@@ -0,0 +1,120 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use sha2::{Sha256,Digest};
+
+use blockdag::{BlockRaw,BlockError,Transaction};
+
+/// The root of an empty tree, i.e. of a block without transaction.
+///
+pub const EMPTY_ROOT: [u8; 32] = [0u8; 32];
+
+/// An inclusion proof: the sibling hashes from the leaf up to the root. The flag tells whether the sibling is on the left.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MerkleProof{
+    pub index: usize,
+    pub siblings: Vec<(bool, [u8; 32])>,
+}
+
+fn hash_leaf(leaf: &[u8; 32]) -> [u8; 32]{
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(leaf);
+    hasher.finalize().into()
+}
+
+fn hash_nodes(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32]{
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// The levels of the tree, from the hashed leaves up to the root. The leaves and the inner nodes are hashed with
+/// different prefixes, and an odd node is moved up as it is, so that no other list of leaves has the same root.
+///
+fn merkle_levels(leaves: &[[u8; 32]]) -> Vec<Vec<[u8; 32]>>{
+
+    let mut levels: Vec<Vec<[u8; 32]>> = vec![leaves.iter().map(hash_leaf).collect()];
+    while levels.last().unwrap().len() > 1 {
+        let level = levels.last().unwrap().chunks(2).map(|pair| {
+            if pair.len() == 2 { hash_nodes(&pair[0], &pair[1]) } else { pair[0] }
+        }).collect();
+        levels.push(level);
+    }
+    levels
+}
+
+/// Function providing the merkle root of the leaves, 'EMPTY_ROOT' for no leaf.
+///
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32]{
+
+    if leaves.is_empty() {
+        return EMPTY_ROOT;
+    }
+    merkle_levels(leaves).last().unwrap()[0]
+}
+
+/// Function providing the merkle root of the transactions of a block, over their hashes.
+///
+pub fn tx_merkle_root(txs: &[Transaction]) -> [u8; 32]{
+    merkle_root(&txs.iter().map(|tx| tx.hash()).collect::<Vec<_>>())
+}
+
+/// Function providing the inclusion proof of the leaf at this index, None if out of range.
+///
+pub fn merkle_proof(leaves: &[[u8; 32]], index: usize) -> Option<MerkleProof>{
+
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let levels = merkle_levels(leaves);
+    let mut siblings: Vec<(bool, [u8; 32])> = Vec::new();
+    let mut position = index;
+    for level in &levels[..levels.len() - 1] {
+        let sibling = position ^ 1;
+        if sibling < level.len() {
+            siblings.push((sibling < position, level[sibling]));
+        }
+        position /= 2;
+    }
+
+    Some(MerkleProof{index, siblings})
+}
+
+/// Whether the proof links the leaf to the root.
+///
+pub fn verify_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &MerkleProof) -> bool{
+
+    let mut hash = hash_leaf(leaf);
+    for &(left, ref sibling) in &proof.siblings {
+        hash = if left { hash_nodes(sibling, &hash) } else { hash_nodes(&hash, sibling) };
+    }
+    hash == *root
+}
+
+/// Function providing the merkle root check of a received block: it's recomputed from the transactions.
+///
+pub fn check_merkle_root(block: &BlockRaw) -> Result<(), BlockError>{
+
+    if block.merkle_root != tx_merkle_root(&block.txs) {
+        return Err(BlockError::BadMerkleRoot(block.name.clone()));
+    }
+    Ok(())
+}
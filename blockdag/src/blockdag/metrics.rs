@@ -0,0 +1,272 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde_json;
+
+use blockdag::{Node,Simulator};
+
+/// Samples collected on one node during a simulation.
+///
+#[derive(Clone, Debug, Default)]
+pub struct NodeStats{
+    pub tips_samples: u64,
+    pub tips_sum: u64,
+    pub tips_max: u64,
+    pub stash_peak: u64,
+    pub colours: HashMap<String,(bool,u64)>,                // last seen colour of a block, and the (virtual) time it was seen so.
+    log_position: usize,                                    // in the colour log of the node.
+}
+
+impl NodeStats {
+
+    /// Sample the node after an event. 'changed' are the blocks just added to this node, whose colour is checked together with
+    /// all the blocks recoloured since the last sample, as recorded by 'calc_blue' in 'Node::colour_log'.
+    ///
+    pub fn sample(&mut self, node: &Node, changed: &[String], stash_size: usize, clock: u64){
+
+        let tips = node.tips.len() as u64;
+        self.tips_samples += 1;
+        self.tips_sum += tips;
+        if tips > self.tips_max {
+            self.tips_max = tips;
+        }
+        if stash_size as u64 > self.stash_peak {
+            self.stash_peak = stash_size as u64;
+        }
+
+        match node.colour_log_since(self.log_position) {
+            Some(log) => {
+                for name in changed.iter().chain(log.iter()) {
+                    if let Some(block) = node.dag.get(name) {
+                        let is_blue = block.read().unwrap().is_blue;
+                        self.observe(name, is_blue, clock);
+                    }
+                }
+            },
+            None => self.finish(node, clock),
+        }
+        self.log_position = node.colour_log_end();
+    }
+
+    /// Last check at the end of a simulation, for the blocks changed after the last sample.
+    ///
+    pub fn finish(&mut self, node: &Node, clock: u64){
+        for (name, block) in &node.dag {
+            let is_blue = block.read().unwrap().is_blue;
+            self.observe(name, is_blue, clock);
+        }
+    }
+
+    fn observe(&mut self, name: &str, is_blue: bool, clock: u64){
+        let changed = match self.colours.get(name) {
+            Some(&(colour,_)) => colour != is_blue,
+            None => true,
+        };
+        if changed {
+            self.colours.insert(name.to_string(), (is_blue, clock));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct NodeMetrics{
+    pub name: String,
+    pub blocks_produced: u64,
+    pub blocks: u64,
+    pub red_blocks: u64,
+    pub red_fraction: f64,
+    pub avg_tips: f64,
+    pub max_tips: u64,
+    pub avg_parents: f64,                                   // average references of the blocks, Genesis excluded.
+    pub avg_anticone_blue: f64,                             // average 'size_of_anticone_blue' of the blue blocks.
+    pub stash_peak: u64,
+    pub avg_time_to_blue: f64,                              // ms from a block mined to it becoming (finally) blue on this node.
+    pub max_time_to_blue: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AggregatedMetrics{
+    pub nodes: u64,
+    pub blocks_produced: u64,
+    pub red_fraction: f64,                                  // average of the nodes.
+    pub avg_tips: f64,
+    pub max_tips: u64,
+    pub avg_parents: f64,
+    pub avg_anticone_blue: f64,
+    pub stash_peak: u64,
+    pub avg_time_to_blue: f64,
+    pub max_time_to_blue: u64,
+    pub converged_blocks: u64,                              // blocks with the same final colour on all nodes.
+    pub diverged_blocks: u64,
+    pub avg_time_to_convergence: f64,                       // ms from a block mined to the last node getting its final colour.
+    pub max_time_to_convergence: u64,
+}
+
+/// Messages sent over all the links of a simulation.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Traffic{
+    pub messages: u64,
+    pub bytes: u64,                                         // approximate, see 'Message::encoded_size'.
+    pub block_transfers: u64,                               // full blocks sent.
+    pub duplicate_blocks: u64,                              // full blocks received while already in the dag or the stash.
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SimMetrics{
+    pub nodes: Vec<NodeMetrics>,
+    pub aggregated: AggregatedMetrics,
+    pub traffic: Traffic,
+}
+
+/// Function providing the metrics report of a finished simulation.
+///
+pub fn sim_metrics(sim: &Simulator) -> SimMetrics{
+
+    let mined_time: HashMap<&str,u64> = sim.mined.iter().map(|(name,_,time)| (name.as_str(), *time)).collect();
+
+    let mut nodes: Vec<NodeMetrics> = Vec::with_capacity(sim.nodes.len());
+
+    // per block: (nodes seeing it blue, nodes seeing it red, latest delay of getting its final colour)
+    let mut convergence: HashMap<String,(u64,u64,u64)> = HashMap::new();
+
+    for (node, stats) in sim.nodes.iter().zip(sim.stats.iter()) {
+        let node_r = node.read().unwrap();
+
+        let mut red_blocks: u64 = 0;
+        let mut anticone_blue_sum: f64 = 0.0;
+        let mut blue_blocks: u64 = 0;
+        let mut time_to_blue_sum: f64 = 0.0;
+        let mut time_to_blue_count: u64 = 0;
+        let mut max_time_to_blue: u64 = 0;
+        let mut parents_sum: f64 = 0.0;
+
+        for block in node_r.dag.values() {
+            let block = block.read().unwrap();
+            parents_sum += block.prev.len() as f64;
+            if block.is_blue {
+                blue_blocks += 1;
+                anticone_blue_sum += block.size_of_anticone_blue as f64;
+            }else{
+                red_blocks += 1;
+            }
+
+            let mined = match mined_time.get(block.name.as_str()) {
+                Some(&time) => time,
+                None => continue,                           // Genesis
+            };
+            let seen = stats.colours.get(&block.name).map_or(sim.clock, |&(_,time)| time);
+            let delay = seen.saturating_sub(mined);
+
+            if block.is_blue {
+                time_to_blue_sum += delay as f64;
+                time_to_blue_count += 1;
+                if delay > max_time_to_blue {
+                    max_time_to_blue = delay;
+                }
+            }
+
+            let entry = convergence.entry(block.name.clone()).or_insert((0,0,0));
+            if block.is_blue { entry.0 += 1; } else { entry.1 += 1; }
+            if delay > entry.2 {
+                entry.2 = delay;
+            }
+        }
+
+        let blocks = node_r.dag.len() as u64;
+        nodes.push(NodeMetrics{
+            name: node_r.name.clone(),
+            blocks_produced: node_r.mined_blocks,
+            blocks,
+            red_blocks,
+            red_fraction: ratio(red_blocks as f64, blocks),
+            avg_tips: ratio(stats.tips_sum as f64, stats.tips_samples),
+            max_tips: stats.tips_max,
+            avg_parents: ratio(parents_sum, blocks.saturating_sub(1)),
+            avg_anticone_blue: ratio(anticone_blue_sum, blue_blocks),
+            stash_peak: stats.stash_peak,
+            avg_time_to_blue: ratio(time_to_blue_sum, time_to_blue_count),
+            max_time_to_blue,
+        });
+    }
+
+    let total_nodes = nodes.len() as u64;
+    let mut converged_blocks: u64 = 0;
+    let mut convergence_sum: f64 = 0.0;
+    let mut max_time_to_convergence: u64 = 0;
+    for &(blues, reds, time) in convergence.values() {
+        if blues + reds == total_nodes && (blues == 0 || reds == 0) {
+            converged_blocks += 1;
+            convergence_sum += time as f64;
+            if time > max_time_to_convergence {
+                max_time_to_convergence = time;
+            }
+        }
+    }
+
+    let aggregated = AggregatedMetrics{
+        nodes: total_nodes,
+        blocks_produced: nodes.iter().map(|n| n.blocks_produced).sum(),
+        red_fraction: ratio(nodes.iter().map(|n| n.red_fraction).sum(), total_nodes),
+        avg_tips: ratio(nodes.iter().map(|n| n.avg_tips).sum(), total_nodes),
+        max_tips: nodes.iter().map(|n| n.max_tips).max().unwrap_or(0),
+        avg_parents: ratio(nodes.iter().map(|n| n.avg_parents).sum(), total_nodes),
+        avg_anticone_blue: ratio(nodes.iter().map(|n| n.avg_anticone_blue).sum(), total_nodes),
+        stash_peak: nodes.iter().map(|n| n.stash_peak).max().unwrap_or(0),
+        avg_time_to_blue: ratio(nodes.iter().map(|n| n.avg_time_to_blue).sum(), total_nodes),
+        max_time_to_blue: nodes.iter().map(|n| n.max_time_to_blue).max().unwrap_or(0),
+        converged_blocks,
+        diverged_blocks: sim.mined.len() as u64 - converged_blocks,
+        avg_time_to_convergence: ratio(convergence_sum, converged_blocks),
+        max_time_to_convergence,
+    };
+
+    SimMetrics{nodes, aggregated, traffic: sim.traffic.clone()}
+}
+
+impl SimMetrics {
+
+    pub fn to_json(&self) -> String{
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// One line per node, plus a last line 'all' for the aggregated metrics. The convergence metrics are only aggregated.
+    ///
+    pub fn to_csv(&self) -> String{
+
+        let mut csv = String::from("node,blocks_produced,blocks,red_blocks,red_fraction,avg_tips,max_tips,avg_parents,avg_anticone_blue,\
+                                    stash_peak,avg_time_to_blue,max_time_to_blue,converged_blocks,diverged_blocks,\
+                                    avg_time_to_convergence,max_time_to_convergence\n");
+        for n in &self.nodes {
+            csv.push_str(&format!("{},{},{},{},{:.4},{:.3},{},{:.3},{:.3},{},{:.1},{},,,,\n",
+                                  n.name, n.blocks_produced, n.blocks, n.red_blocks, n.red_fraction, n.avg_tips, n.max_tips,
+                                  n.avg_parents, n.avg_anticone_blue, n.stash_peak, n.avg_time_to_blue, n.max_time_to_blue));
+        }
+
+        let a = &self.aggregated;
+        csv.push_str(&format!("all,{},,,{:.4},{:.3},{},{:.3},{:.3},{},{:.1},{},{},{},{:.1},{}\n",
+                              a.blocks_produced, a.red_fraction, a.avg_tips, a.max_tips, a.avg_parents, a.avg_anticone_blue,
+                              a.stash_peak, a.avg_time_to_blue, a.max_time_to_blue, a.converged_blocks, a.diverged_blocks,
+                              a.avg_time_to_convergence, a.max_time_to_convergence));
+        csv
+    }
+}
+
+fn ratio(sum: f64, count: u64) -> f64{
+    if count == 0 { 0.0 } else { sum / count as f64 }
+}
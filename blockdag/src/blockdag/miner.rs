@@ -0,0 +1,103 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use ed25519_dalek::{Signature,Signer,SigningKey,Verifier,VerifyingKey};
+
+use blockdag::{Node,BlockRaw,BlockError,header_digest};
+
+/// The identity of a miner: an ed25519 key, which signs the headers of its blocks.
+///
+#[derive(Clone)]
+pub struct MinerKey{
+    key: SigningKey,
+}
+
+impl MinerKey {
+
+    /// The key is derived from the seed, in simulation a seed per node is enough.
+    ///
+    pub fn from_seed(seed: &[u8; 32]) -> MinerKey{
+        MinerKey{key: SigningKey::from_bytes(seed)}
+    }
+
+    pub fn public_key(&self) -> [u8; 32]{
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// Set the miner of the header and sign it. With proof of work, this comes after the nonce is found.
+    ///
+    pub fn sign(&self, header: &mut BlockRaw){
+        header.miner = Some(self.public_key());
+        header.signature = Some(self.key.sign(&header_digest(header)).to_bytes().to_vec());
+    }
+}
+
+/// Function providing the signature check of a header. An anonymous header (no miner) must have no signature.
+///
+pub fn check_signature(header: &BlockRaw) -> Result<(), BlockError>{
+
+    let valid = match (&header.miner, &header.signature) {
+        (None, None) => true,
+        (Some(miner), Some(signature)) => {
+            let key = VerifyingKey::from_bytes(miner);
+            let signature = Signature::from_slice(signature);
+            match (key, signature) {
+                (Ok(key), Ok(signature)) => key.verify(&header_digest(header), &signature).is_ok(),
+                _ => false,
+            }
+        },
+        _ => false,
+    };
+
+    if !valid {
+        return Err(BlockError::BadSignature(header.name.clone()));
+    }
+    Ok(())
+}
+
+/// The blocks of a miner in the local dag, with their current colours.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MinerStats{
+    pub blocks: u64,
+    pub blue: u64,
+    pub red: u64,
+}
+
+impl Node {
+
+    /// Function providing the statistics of each miner, by public key. The anonymous blocks aren't counted.
+    /// They're computed on call, since 'calc_blue' may recolour any block of the anticone of a new one.
+    ///
+    pub fn miner_stats(&self) -> HashMap<[u8; 32], MinerStats>{
+
+        let mut stats: HashMap<[u8; 32], MinerStats> = HashMap::new();
+        for block in self.dag.values() {
+            let block = block.read().unwrap();
+            if let Some(miner) = block.miner {
+                let miner_stats = stats.entry(miner).or_default();
+                miner_stats.blocks += 1;
+                if block.is_blue {
+                    miner_stats.blue += 1;
+                } else {
+                    miner_stats.red += 1;
+                }
+            }
+        }
+        stats
+    }
+}
@@ -25,15 +25,63 @@ mod cardinality;
 mod anticone;
 mod hourglass;
 mod calcblue;
+mod render;
+mod dump;
+mod netsim;
+mod topology;
+mod metrics;
+mod ordering;
+mod convergence;
+mod attack;
+mod partition;
+mod sync;
+mod ibd;
+mod net;
+mod inventory;
+mod parents;
+mod error;
+mod template;
+mod pow;
+mod timestamp;
+mod ledger;
+mod mempool;
+mod merkle;
+mod miner;
+mod reward;
+mod virtualblock;
+mod chain;
+mod ghostdag;
 
 pub use self::block::{Block,BlockRaw,MaxMin,append_maps,remove_past_future,sorted_keys_by_height,get_ltpq,get_stpq};
-pub use self::node::{Node,node_add_block,update_tips,handle_block_rx};
+pub use self::node::{Node,node_add_block,node_add_raw,update_tips,handle_block_rx,MAX_COLOUR_LOG};
 pub use self::dagsim::{dag_add_block,dag_print,dag_blue_print,dag_red_print};
 pub use self::cardinality::{sizeof_pastset,step_one_past};
 pub use self::anticone::{tips_anticone,tips_anticone_blue,anticone_blue};
 //pub use self::hourglass::{get_nearest_hourglass};
 pub use self::calcblue::{calc_blue};
-
-
-
-
+pub use self::render::{dag_render_svg,dag_render_html,dag_render_to_file};
+pub use self::dump::{NodeDump,BlockDump,DumpError,node_dump,node_to_json,node_from_dump,node_from_json,node_save_json,node_load_json};
+pub use self::netsim::{Simulator,SimConfig,PowConfig,Latency};
+pub use self::topology::{Topology,Link};
+pub use self::metrics::{NodeStats,NodeMetrics,AggregatedMetrics,Traffic,SimMetrics,sim_metrics};
+pub use self::ordering::{dag_order};
+pub use self::convergence::{ConvergenceReport,check_convergence};
+pub use self::attack::{Attacker,AttackerConfig,AttackRelease,AttackReport,sim_attack_report};
+pub use self::partition::{Partition,PartitionConfig,PartitionCut,PartitionReport,sim_partition_report};
+pub use self::sync::{Message,StashConfig,BlockStash,SyncResult,handle_message,handle_request,MAX_HEADERS};
+pub use self::ibd::{DagSync};
+pub use self::net::{NetNode,NetError,Frame,PROTOCOL_VERSION,MAX_FRAME};
+pub use self::inventory::{Relay,PeerInventory,handle_inv,request_due,MAX_KNOWN_BLOCKS};
+pub use self::parents::{ParentSelector,ParentStrategy,AllTips,TopScore,BlueTips,RandomSubset,MaxParents};
+pub use self::error::{BlockError,TxError};
+pub use self::template::{BlockTemplate};
+pub use self::pow::{DaaConfig,header_digest,header_hash,check_pow,solve,target_for,expected_hashes,next_target,target_accepted};
+pub use self::timestamp::{TimeRules,past_blue_window,median_time_past,check_timestamp};
+pub use self::ledger::{OutPoint,TxOut,Transaction,Ledger,LedgerUpdate,ledger_from_node};
+pub use self::mempool::{Mempool,MempoolConfig};
+pub use self::merkle::{EMPTY_ROOT,MerkleProof,merkle_root,tx_merkle_root,merkle_proof,verify_proof,check_merkle_root};
+pub use self::miner::{MinerKey,MinerStats,check_signature};
+pub use self::reward::{RewardConfig,Rewards,rewards_from_node};
+pub use self::virtualblock::{VirtualBlock};
+pub use self::chain::{ChainChanged,update_selected_chain};
+pub use self::ghostdag::{ColouringEngine,ColouringError,CalcBlue,Ghostdag,GhostdagData,node_add_block_with};
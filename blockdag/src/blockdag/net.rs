@@ -0,0 +1,369 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::{BufRead,BufReader,Read,Write};
+use std::net::{TcpListener,TcpStream,SocketAddr};
+use std::sync::{Arc,RwLock,Mutex};
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::thread;
+use std::time::{Instant,SystemTime,UNIX_EPOCH};
+
+use serde_json;
+
+use blockdag::{Node,BlockRaw,BlockError,Message,BlockStash,StashConfig,DagSync,PeerInventory};
+use blockdag::{TopScore,handle_message,handle_request,handle_inv};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+const HEADERS_BATCH: usize = 500;
+pub const MAX_FRAME: u64 = 32 * 1024 * 1024;                // bytes of a frame line, a peer sending a longer one is dropped.
+
+/// What goes on the wire: one JSON frame per line.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Frame{
+    Hello{version: u32, name: String, genesis: String, k: i32},
+    Message(Message),
+}
+
+#[derive(Debug)]
+pub enum NetError{
+    Io(io::Error),
+    Json(serde_json::Error),
+    Handshake(String),
+    FrameTooLong(u64),                                      // no end of line within 'MAX_FRAME' bytes.
+}
+
+impl fmt::Display for NetError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetError::Io(ref e) => write!(f, "io error: {}", e),
+            NetError::Json(ref e) => write!(f, "json error: {}", e),
+            NetError::Handshake(ref reason) => write!(f, "handshake failed: {}", reason),
+            NetError::FrameTooLong(max) => write!(f, "frame longer than {} bytes", max),
+        }
+    }
+}
+
+impl error::Error for NetError {}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> NetError {
+        NetError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for NetError {
+    fn from(e: serde_json::Error) -> NetError {
+        NetError::Json(e)
+    }
+}
+
+struct Peer{
+    name: String,
+    writer: Mutex<TcpStream>,
+    inventory: Mutex<PeerInventory>,
+}
+
+impl Peer {
+
+    fn send(&self, message: Message){
+        self.mark_known(&message);
+        if let Err(e) = write_frame(&mut self.writer.lock().unwrap(), &Frame::Message(message)) {
+            warn!("net: failed to send to {}: {}", self.name, e);
+        }
+    }
+
+    /// A block sent or announced to this peer, or received or announced from it, is known by this peer.
+    ///
+    fn mark_known(&self, message: &Message){
+        let mut inventory = self.inventory.lock().unwrap();
+        match *message {
+            Message::Block(ref block) => inventory.mark_known(&block.name),
+            Message::Inv(ref names) => names.iter().for_each(|name| inventory.mark_known(name)),
+            _ => {},
+        }
+    }
+}
+
+struct Shared{
+    node: Arc<RwLock<Node>>,
+    k: i32,
+    genesis: String,
+    stash: Mutex<BlockStash>,
+    in_flight: Mutex<HashMap<String, u64>>,                 // blocks requested with a 'GetData' and not received yet, with the request time.
+    peers: Mutex<HashMap<usize, Arc<Peer>>>,
+    next_peer: AtomicUsize,
+    started: Instant,
+}
+
+/// A node on a TCP network. It listens for peers, handshakes with them (protocol version, genesis, k), syncs its dag
+/// from each new peer, and announces the blocks it accepts to the peers which don't know them yet ('Inv'). A peer requests
+/// the announced blocks it misses ('GetData'), so that a block is sent at most once over a connection.
+///
+/// Each peer connection has a reader thread, feeding the received blocks through the same sync layer as the simulator.
+///
+pub struct NetNode{
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+}
+
+impl NetNode {
+
+    /// Listen on 'addr', i.e. "127.0.0.1:7000". The port 0 gives a free port, see 'local_addr'.
+    /// The node must already have its Genesis block.
+    ///
+    pub fn start(node: Arc<RwLock<Node>>, k: i32, addr: &str) -> Result<NetNode, NetError>{
+
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+
+        let genesis = {
+            let node_r = node.read().unwrap();
+            let genesis = node_r.dag.values().find(|block| block.read().unwrap().height == 0).map(|block| block.read().unwrap().name.clone());
+            genesis.ok_or_else(|| NetError::Handshake(format!("node {} has no genesis block", node_r.name)))?
+        };
+
+        let shared = Arc::new(Shared{
+            node,
+            k,
+            genesis,
+            stash: Mutex::new(BlockStash::new(StashConfig::default())),
+            in_flight: Mutex::new(HashMap::new()),
+            peers: Mutex::new(HashMap::new()),
+            next_peer: AtomicUsize::new(0),
+            started: Instant::now(),
+        });
+
+        let shared_clone = Arc::clone(&shared);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let shared = Arc::clone(&shared_clone);
+                        thread::spawn(move || {
+                            if let Err(e) = handle_connection(shared, stream) {
+                                warn!("net: incoming connection closed: {}", e);
+                            }
+                        });
+                    },
+                    Err(e) => warn!("net: accept failed: {}", e),
+                }
+            }
+        });
+
+        info!("net: node {} listening on {}", shared.node.read().unwrap().name, local_addr);
+        Ok(NetNode{shared, local_addr})
+    }
+
+    pub fn local_addr(&self) -> SocketAddr{
+        self.local_addr
+    }
+
+    pub fn node(&self) -> Arc<RwLock<Node>>{
+        Arc::clone(&self.shared.node)
+    }
+
+    pub fn peers(&self) -> usize{
+        self.shared.peers.lock().unwrap().len()
+    }
+
+    /// Connect to a peer. Return once the handshake is done, the connection then lives in its own thread.
+    ///
+    pub fn connect(&self, addr: &str) -> Result<(), NetError>{
+
+        let stream = TcpStream::connect(addr)?;
+        let (peer_id, reader) = handshake(&self.shared, stream)?;
+
+        let shared = Arc::clone(&self.shared);
+        thread::spawn(move || {
+            if let Err(e) = serve_peer(&shared, peer_id, reader) {
+                warn!("net: connection closed: {}", e);
+            }
+            shared.peers.lock().unwrap().remove(&peer_id);
+        });
+        Ok(())
+    }
+
+    /// Mine a new block on top of the best K+1 tips (by score), and announce it to all the peers.
+    /// Nothing is announced if the block is refused, e.g. for a name already used.
+    ///
+    pub fn mine(&self, block_name: &str) -> Result<BlockRaw, BlockError>{
+
+        let raw = {
+            let mut node_w = self.shared.node.write().unwrap();
+            node_w.clock = unix_time_ms();
+            node_w.mine_block(block_name, &mut TopScore{n: (self.shared.k+1) as usize}, self.shared.k)?
+        };
+
+        announce(&self.shared, None, ::std::slice::from_ref(&raw.name));
+        Ok(raw)
+    }
+}
+
+fn write_frame(stream: &mut TcpStream, frame: &Frame) -> Result<(), NetError>{
+    let mut line = serde_json::to_string(frame)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+fn read_frame(reader: &mut BufReader<TcpStream>) -> Result<Option<Frame>, NetError>{
+    let mut line = String::new();
+    let size = reader.by_ref().take(MAX_FRAME).read_line(&mut line)?;
+    if size == 0 {
+        return Ok(None);                                    // connection closed.
+    }
+    if size as u64 == MAX_FRAME && !line.ends_with('\n') {
+        return Err(NetError::FrameTooLong(MAX_FRAME));
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+fn handle_connection(shared: Arc<Shared>, stream: TcpStream) -> Result<(), NetError>{
+
+    let (peer_id, reader) = handshake(&shared, stream)?;
+    let result = serve_peer(&shared, peer_id, reader);
+    shared.peers.lock().unwrap().remove(&peer_id);
+    result
+}
+
+/// Both sides send their 'Hello' firstly, and check the other's one. Then the peer is registered, and the dag sync starts.
+///
+fn handshake(shared: &Arc<Shared>, stream: TcpStream) -> Result<(usize, BufReader<TcpStream>), NetError>{
+
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let name = shared.node.read().unwrap().name.clone();
+    write_frame(&mut writer, &Frame::Hello{version: PROTOCOL_VERSION, name, genesis: shared.genesis.clone(), k: shared.k})?;
+
+    let peer_name = match read_frame(&mut reader)? {
+        Some(Frame::Hello{version, name, genesis, k}) => {
+            if version != PROTOCOL_VERSION {
+                return Err(NetError::Handshake(format!("peer {} protocol version {}, expected {}", name, version, PROTOCOL_VERSION)));
+            }
+            if genesis != shared.genesis {
+                return Err(NetError::Handshake(format!("peer {} genesis {}, expected {}", name, genesis, shared.genesis)));
+            }
+            if k != shared.k {
+                return Err(NetError::Handshake(format!("peer {} k={}, expected k={}", name, k, shared.k)));
+            }
+            name
+        },
+        Some(frame) => return Err(NetError::Handshake(format!("expected hello, got {:?}", frame))),
+        None => return Err(NetError::Handshake(String::from("connection closed"))),
+    };
+
+    let peer_id = shared.next_peer.fetch_add(1, Ordering::SeqCst);
+    shared.peers.lock().unwrap().insert(peer_id, Arc::new(Peer{name: peer_name.clone(), writer: Mutex::new(writer), inventory: Mutex::new(PeerInventory::default())}));
+    info!("net: node {} connected to peer {}", shared.node.read().unwrap().name, peer_name);
+
+    Ok((peer_id, reader))
+}
+
+/// Reader loop of a peer connection.
+///
+fn serve_peer(shared: &Arc<Shared>, peer_id: usize, mut reader: BufReader<TcpStream>) -> Result<(), NetError>{
+
+    let peer = match shared.peers.lock().unwrap().get(&peer_id) {
+        Some(peer) => Arc::clone(peer),
+        None => return Ok(()),
+    };
+
+    let mut dag_sync = DagSync::new(HEADERS_BATCH);
+    peer.send(dag_sync.start());
+
+    while let Some(frame) = read_frame(&mut reader)? {
+        let message = match frame {
+            Frame::Message(message) => message,
+            Frame::Hello{..} => return Err(NetError::Handshake(format!("unexpected hello from {}", peer.name))),
+        };
+
+        peer.mark_known(&message);
+        let clock = shared.started.elapsed().as_millis() as u64;
+
+        // the node lock is released before sending anything, so that a slow peer never blocks the node.
+        let result = match message {
+            Message::Block(_) | Message::Tips(_) | Message::Headers(_) => {
+                let mut node_w = shared.node.write().unwrap();
+                node_w.clock = unix_time_ms();
+                let mut stash = shared.stash.lock().unwrap();
+                match message {
+                    Message::Block(ref block) => {
+                        shared.in_flight.lock().unwrap().remove(&block.name);
+                        handle_message(message, &mut node_w, &mut stash, shared.k, clock)
+                    },
+                    _ => dag_sync.handle_reply(message, &mut node_w, &mut stash, shared.k, clock),
+                }
+            },
+            Message::Inv(ref names) => {
+                let get_data = {
+                    let node_r = shared.node.read().unwrap();
+                    let stash = shared.stash.lock().unwrap();
+                    handle_inv(names, &node_r, &stash, &mut shared.in_flight.lock().unwrap(), clock)
+                };
+                if let Some(get_data) = get_data {
+                    peer.send(get_data);
+                }
+                continue;
+            },
+            _ => {
+                let replies = handle_request(&message, &shared.node.read().unwrap());
+                for reply in replies {
+                    peer.send(reply);
+                }
+                continue;
+            },
+        };
+
+        for reply in result.replies {
+            peer.send(reply);
+        }
+
+        announce(shared, Some(peer_id), &result.added);
+    }
+
+    info!("net: peer {} disconnected", peer.name);
+    Ok(())
+}
+
+/// Announce blocks to all the peers which don't know them yet, except the one they came from.
+///
+fn announce(shared: &Arc<Shared>, except: Option<usize>, names: &[String]){
+
+    if names.is_empty() {
+        return;
+    }
+
+    let peers: Vec<Arc<Peer>> = shared.peers.lock().unwrap().iter().filter(|(id,_)| Some(**id) != except).map(|(_,peer)| Arc::clone(peer)).collect();
+    for peer in peers {
+        let names = peer.inventory.lock().unwrap().announce(names);
+        if !names.is_empty() {
+            peer.send(Message::Inv(names));
+        }
+    }
+}
+
+/// The node clock of a networked node: ms since the unix epoch, the block timestamps must be comparable among nodes.
+///
+fn unix_time_ms() -> u64{
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
@@ -0,0 +1,737 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet,BinaryHeap};
+use std::cmp::Ordering;
+use std::sync::{Arc,RwLock};
+
+use rand::{Rng,SeedableRng,XorShiftRng};
+
+use blockdag::{Node,BlockRaw,Topology,Link,NodeStats,ConvergenceReport,Attacker,AttackerConfig,AttackRelease};
+use blockdag::{Partition,PartitionConfig,PartitionCut,Message,BlockStash,StashConfig,Relay,PeerInventory,Traffic};
+use blockdag::{ParentSelector,ParentStrategy,TopScore,DaaConfig,TimeRules,MinerKey,BlockError};
+use blockdag::{next_target,expected_hashes,solve,target_for};
+use blockdag::{node_add_block,handle_message,handle_inv,request_due,check_convergence};
+
+const RECONCILE_CHECK_INTERVAL: u64 = 50;                   // ms between two checks of the nodes sync and agreement, after a network split is healed.
+
+/// Block propagation delay of one link, in milliseconds of the virtual clock.
+///
+#[derive(Clone, Debug)]
+pub enum Latency{
+    Constant(u64),
+    Uniform(u64,u64),                                       // min, max (both included).
+    Exponential(u64),                                       // mean.
+}
+
+impl Latency {
+
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> u64{
+        match *self {
+            Latency::Constant(delay) => delay,
+            Latency::Uniform(min,max) => if max > min { rng.gen_range(min, max+1) } else { min },
+            Latency::Exponential(mean) => exponential(rng, mean as f64) as u64,
+        }
+    }
+}
+
+/// Simulated proof of work: each node finds blocks at the rate of its hash rate over the difficulty of its current template.
+/// The attacker doesn't mine with proof of work.
+///
+#[derive(Clone, Debug)]
+pub struct PowConfig{
+    pub daa: DaaConfig,
+    pub hash_rates: Vec<f64>,                               // hashes per ms of each node, 1.0 for a node without entry.
+}
+
+impl PowConfig {
+
+    /// The same hash rate for all the nodes, with the initial target of the given block interval.
+    ///
+    pub fn uniform(nodes: usize, hash_rate: f64, block_interval: u64) -> PowConfig{
+        PowConfig{
+            daa: DaaConfig{
+                block_interval,
+                initial_target: target_for(hash_rate * nodes as f64 * block_interval as f64),
+                ..DaaConfig::default()
+            },
+            hash_rates: vec![hash_rate; nodes],
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SimConfig{
+    pub nodes: usize,                                       // how many nodes to simulate.
+    pub blocks: u64,                                        // how many blocks mining for this simulation.
+    pub k: i32,
+    pub block_interval: u64,                                // mean time (ms) between two blocks of the whole network. blocks production is a poisson process.
+    pub latency: Latency,                                   // latency of the links, except the inter-region links of 'Topology::Regions'.
+    pub topology: Topology,
+    pub seed: u64,
+    pub attacker: Option<AttackerConfig>,                   // an attacker withholding its blocks, see 'Attacker'.
+    pub partition: Option<PartitionConfig>,                 // a network split for a while, see 'Partition'.
+    pub stash: StashConfig,
+    pub relay: Relay,
+    pub parents: ParentStrategy,                            // how the miners pick the references of their blocks.
+    pub pow: Option<PowConfig>,                             // None: blocks are found every 'block_interval' ms in average by a random node.
+    pub time_rules: Option<TimeRules>,                      // timestamp checks of the nodes. the block timestamps are the virtual time.
+    pub signatures: bool,                                   // each node gets a miner key and signs its blocks, see 'Node::miner_stats'.
+}
+
+impl Default for SimConfig {
+
+    fn default() -> SimConfig {
+        SimConfig{
+            nodes: 100,
+            blocks: 1000,
+            k: 3,
+            block_interval: 250,
+            latency: Latency::Uniform(20, 400),
+            topology: Topology::FullMesh,
+            seed: 0,
+            attacker: None,
+            partition: None,
+            stash: StashConfig::default(),
+            relay: Relay::Push,
+            parents: ParentStrategy::KPlusOne,
+            pow: None,
+            time_rules: None,
+            signatures: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum EventKind{
+    Mine,
+    Find{miner: usize, round: u64},                         // with proof of work, the miner finds a block, unless its template changed meanwhile. the attacker is the miner after the nodes.
+    Release,                                                // the attacker publishes its withheld blocks.
+    Heal,                                                   // end of the network split.
+    Check,                                                  // check whether the nodes agree again after the heal.
+    Deliver{from: usize, to: usize, message: Box<Message>},
+}
+
+#[derive(Debug)]
+struct Event{
+    time: u64,
+    seq: u64,                                               // tie breaker, events of same time are handled in scheduling order.
+    kind: EventKind,
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.time == other.time && self.seq == other.seq
+    }
+}
+
+impl Eq for Event {}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Event) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Event {
+    // reversed, so the BinaryHeap pops the earliest event firstly.
+    fn cmp(&self, other: &Event) -> Ordering {
+        other.time.cmp(&self.time).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Discrete-event simulation of a network of nodes, with a virtual clock and a seeded random generator,
+/// so that a simulation with the same config always gives the same result.
+///
+/// A mined block is sent to the miner's peers. Except in a full mesh, where the miner's peers are already everybody,
+/// a node relays each block it accepts to all its peers but the one it came from (gossip). With 'Relay::Inventory',
+/// blocks are announced by name and only sent on request, so that a block goes at most once over a link.
+///
+/// With an attacker, each block is mined by the attacker with probability 'hash_fraction' until the release. The attacker is
+/// linked to every honest node, its index is 'nodes.len()'.
+///
+/// With a partition, the blocks sent across the cut during the split are delayed until the heal, or dropped.
+///
+pub struct Simulator{
+    pub config: SimConfig,
+    pub clock: u64,                                         // virtual time in milliseconds.
+    pub nodes: Vec<Arc<RwLock<Node>>>,
+    pub links: Vec<Vec<Link>>,                              // peers of each node.
+    pub stashes: Vec<BlockStash>,
+    pub mined: Vec<(String, usize, u64)>,                   // (block name, miner, mined time), in mining order.
+    pub stats: Vec<NodeStats>,                              // samples of each node for the metrics report.
+    pub attacker: Option<Attacker>,
+    pub partition: Option<Partition>,
+    pub traffic: Traffic,
+    inventories: Vec<HashMap<usize, PeerInventory>>,        // for each node, what its peers know. not tracked in a full mesh, where nothing is relayed.
+    in_flight: Vec<HashMap<String, u64>>,                   // for each node, the blocks requested and not received yet, with the request time.
+    selectors: Vec<Box<dyn ParentSelector>>,                // parent selection of each node.
+    rounds: Vec<u64>,                                       // with proof of work, the current mining round of each node.
+    events: BinaryHeap<Event>,
+    rng: XorShiftRng,
+    seq: u64,
+}
+
+impl Simulator {
+
+    pub fn new(config: SimConfig) -> Simulator{
+
+        let seed = [config.seed as u32 ^ 0x193a_6754, (config.seed >> 32) as u32 ^ 0xa8a7_d469, 0x9783_0e05, 0x113b_a7bb];
+
+        let mut nodes = Vec::with_capacity(config.nodes);
+        let mut stashes = Vec::with_capacity(config.nodes);
+        for number in 0..config.nodes {
+            let node = Node::init(&format!("node{}", number));
+            node_add_block("Genesis", &Vec::new(), &mut node.write().unwrap(), config.k, true);
+            node.write().unwrap().daa = config.pow.as_ref().map(|pow| pow.daa.clone());
+            node.write().unwrap().time_rules = config.time_rules.clone();
+            if config.signatures {
+                node.write().unwrap().miner_key = Some(miner_key(config.seed, number));
+            }
+            nodes.push(node);
+            stashes.push(BlockStash::new(config.stash.clone()));
+        }
+
+        let mut rng = XorShiftRng::from_seed(seed);
+        let links = config.topology.build(config.nodes, &config.latency, &mut rng);
+        let stats = vec![NodeStats::default(); config.nodes];
+        let attacker = config.attacker.clone().map(|attacker_config| Attacker::new(attacker_config, config.k));
+        if let Some(ref attacker) = attacker {
+            let mut node_w = attacker.node.write().unwrap();
+            node_w.daa = config.pow.as_ref().map(|pow| pow.daa.clone());
+            node_w.time_rules = config.time_rules.clone();
+        }
+        let partition = config.partition.clone().map(Partition::new);
+
+        let nodes_count = config.nodes;
+        let selectors = (0..nodes_count).map(|number| config.parents.selector(config.k, config.seed ^ number as u64)).collect();
+        let mut sim = Simulator{
+            config,
+            clock: 0,
+            nodes,
+            links,
+            stashes,
+            mined: Vec::new(),
+            stats,
+            attacker,
+            partition,
+            traffic: Traffic::default(),
+            inventories: vec![HashMap::new(); nodes_count],
+            in_flight: vec![HashMap::new(); nodes_count],
+            selectors,
+            rounds: vec![0; nodes_count + 1],
+            events: BinaryHeap::new(),
+            rng,
+            seq: 0,
+        };
+
+        if sim.config.blocks > 0 {
+            if sim.config.pow.is_some() {
+                for miner in 0..nodes_count {
+                    sim.schedule_find(miner);
+                }
+                if sim.attacker.is_some() {
+                    sim.schedule_find(nodes_count);
+                }
+            }else{
+                let delay = sim.next_block_delay();
+                sim.schedule(delay, EventKind::Mine);
+            }
+        }
+        if let Some(AttackerConfig{release: AttackRelease::AtTime(time), ..}) = sim.config.attacker {
+            sim.schedule(time, EventKind::Release);
+        }
+        if let Some(heal_time) = sim.config.partition.as_ref().map(|partition| partition.heal_time()) {
+            sim.schedule(heal_time, EventKind::Heal);
+        }
+
+        sim
+    }
+
+    /// Run the simulation until all blocks are mined and propagated.
+    ///
+    pub fn run(&mut self){
+        while self.step() {}
+
+        for (node, stats) in self.nodes.iter().zip(self.stats.iter_mut()) {
+            stats.finish(&node.read().unwrap(), self.clock);
+        }
+
+        info!("simulation done. clock={}ms, blocks={}, nodes={}", self.clock, self.mined.len(), self.nodes.len());
+    }
+
+    /// Handle the next event. Return false when there's no more event.
+    ///
+    pub fn step(&mut self) -> bool{
+
+        let event = match self.events.pop() {
+            Some(event) => event,
+            None => {
+                // an attacker still withholding blocks at the end releases them anyway.
+                if self.attacker.as_ref().is_some_and(|attacker| attacker.is_active()) {
+                    self.release();
+                    return true;
+                }
+                return false;
+            },
+        };
+        self.clock = event.time;
+
+        match event.kind {
+            EventKind::Mine => {
+                let hash_fraction = self.attacker.as_ref().filter(|attacker| attacker.is_active()).map(|attacker| attacker.config.hash_fraction);
+                match hash_fraction {
+                    Some(fraction) if self.rng.gen::<f64>() < fraction => self.mine_private(),
+                    _ => {
+                        let miner = self.rng.gen_range(0, self.nodes.len());
+                        self.mine(miner);
+                    },
+                }
+
+                if (self.mined.len() as u64) < self.config.blocks {
+                    let delay = self.next_block_delay();
+                    self.schedule(delay, EventKind::Mine);
+                }
+            },
+            EventKind::Find{miner, round} => {
+                if round == self.rounds[miner] && (self.mined.len() as u64) < self.config.blocks {
+                    if miner == self.nodes.len() {
+                        self.mine_private();
+                    }else{
+                        self.mine(miner);
+                    }
+                    self.schedule_find(miner);
+                }
+            },
+            EventKind::Release => {
+                if self.attacker.as_ref().is_some_and(|attacker| attacker.is_active()) {
+                    self.release();
+                }
+            },
+            EventKind::Heal => self.heal(),
+            EventKind::Check => {
+                let done = {
+                    let guards = self.nodes.iter().map(|n| n.read().unwrap()).collect::<Vec<_>>();
+                    let nodes = guards.iter().map(|g| &**g).collect::<Vec<&Node>>();
+                    self.partition.as_mut().unwrap().check(&nodes, self.clock)
+                };
+                if done {
+                    info!("simulator: clock={}ms. nodes reconciled after the network split", self.clock);
+                }else if !self.events.is_empty() {
+                    self.schedule(RECONCILE_CHECK_INTERVAL, EventKind::Check);
+                }
+            },
+            EventKind::Deliver{from, to, message} => {
+                let message = *message;
+                let inventory = self.config.relay == Relay::Inventory;
+                let result = {
+                    let mut node_w = self.nodes[to].write().unwrap();
+
+                    node_w.clock = self.clock;
+
+                    let mut get_data: Option<Message> = None;
+                    match message {
+                        Message::Block(ref block) => {
+                            if node_w.dag.contains_key(&block.name) || self.stashes[to].contains(&block.name) {
+                                self.traffic.duplicate_blocks += 1;
+                            }
+                            self.in_flight[to].remove(&block.name);
+                        },
+                        Message::Inv(ref names) => {
+                            get_data = handle_inv(names, &node_w, &self.stashes[to], &mut self.in_flight[to], self.clock);
+                        },
+                        _ => {},
+                    }
+                    if self.tracks_inventory() {
+                        mark_known(&mut self.inventories[to], from, &message);
+                    }
+
+                    let mut result = handle_message(message, &mut node_w, &mut self.stashes[to], self.config.k, self.clock);
+                    result.replies.extend(get_data);
+                    self.stats[to].sample(&node_w, &result.added, self.stashes[to].len(), self.clock);
+                    result
+                };
+
+                // new tips, new template: the time to find a block is sampled again (the mining process is memoryless).
+                if self.config.pow.is_some() && !result.added.is_empty() {
+                    self.schedule_find(to);
+                }
+
+                // replies (requests of missing parents, requested blocks) go back to the sender. the attacker doesn't answer.
+                if from < self.nodes.len() {
+                    for reply in result.replies {
+                        // with inventory, a missing parent already requested from another peer is not requested again.
+                        let reply = match reply {
+                            Message::GetBlocks(names) if inventory => {
+                                let timeout = self.config.stash.request_timeout;
+                                let names: Vec<String> = names.into_iter().filter(|name| request_due(name, &mut self.in_flight[to], timeout, self.clock)).collect();
+                                if names.is_empty() {
+                                    continue;
+                                }
+                                Message::GetBlocks(names)
+                            },
+                            reply => reply,
+                        };
+                        let latency = self.links[to].iter().find(|link| link.to == from).map_or(self.config.latency.clone(), |link| link.latency.clone());
+                        let delay = latency.sample(&mut self.rng);
+                        self.transmit(to, from, delay, reply);
+                    }
+                }
+
+                if let Topology::FullMesh = self.config.topology {
+                    return true;
+                }
+
+                // gossip: relay the accepted blocks.
+                if inventory {
+                    self.announce(to, Some(from), &result.added);
+                }else{
+                    for name in &result.added {
+                        let raw = self.nodes[to].read().unwrap().dag.get(name).unwrap().read().unwrap().to_raw();
+                        self.send(to, Some(from), &raw);
+                    }
+                }
+            },
+        }
+
+        true
+    }
+
+    /// Compare the current views of all the nodes, node0 is the reference.
+    ///
+    pub fn check_convergence(&self) -> ConvergenceReport{
+        let guards = self.nodes.iter().map(|n| n.read().unwrap()).collect::<Vec<_>>();
+        let nodes = guards.iter().map(|g| &**g).collect::<Vec<&Node>>();
+        check_convergence(&nodes)
+    }
+
+    /// The miner creates a new block on top of the tips picked by its parent selector (by default its best K+1 tips by score),
+    /// and sends it to its peers.
+    ///
+    fn mine(&mut self, miner: usize){
+
+        let block_name = format!("{:04}", self.mined.len()+1);
+        let k = self.config.k;
+
+        let new_block_raw = {
+            let mut node_w = self.nodes[miner].write().unwrap();
+            node_w.clock = self.clock;
+
+            let pow = self.config.pow.is_some();
+            let raw = match mine_on(&mut node_w, &block_name, &mut *self.selectors[miner], k, pow, &mut self.rng) {
+                Ok(raw) => raw,
+                Err(e) => {
+                    warn!("simulator: clock={}ms. node{} failed to mine block {}: {}", self.clock, miner, block_name, e);
+                    return;
+                },
+            };
+            self.stats[miner].sample(&node_w, ::std::slice::from_ref(&block_name), self.stashes[miner].len(), self.clock);
+            raw
+        };
+
+        debug!("simulator: clock={}ms. node{} mined block {}", self.clock, miner, block_name);
+        self.mined.push((block_name, miner, self.clock));
+
+        if self.config.relay == Relay::Inventory {
+            self.announce(miner, None, ::std::slice::from_ref(&new_block_raw.name));
+        }else{
+            self.send(miner, None, &new_block_raw);
+        }
+    }
+
+    /// The attacker mines a new block on its own tips, and withholds it.
+    ///
+    fn mine_private(&mut self){
+
+        let block_name = format!("{:04}", self.mined.len()+1);
+        let k = self.config.k;
+        let clock = self.clock;
+
+        let pow = self.config.pow.is_some();
+        let release = {
+            let attacker = self.attacker.as_mut().unwrap();
+            {
+                let mut node_w = attacker.node.write().unwrap();
+                node_w.clock = clock;
+                match mine_on(&mut node_w, &block_name, &mut TopScore{n: (k+1) as usize}, k, pow, &mut self.rng) {
+                    Ok(raw) => attacker.withheld.push(raw),
+                    Err(e) => {
+                        warn!("simulator: clock={}ms. attacker failed to mine block {}: {}", clock, block_name, e);
+                        return;
+                    },
+                }
+            }
+            attacker.blocks.push(block_name.clone());
+            attacker.release_due(clock)
+        };
+
+        debug!("simulator: clock={}ms. attacker mined block {}, withheld={}", clock, block_name, self.attacker.as_ref().unwrap().withheld.len());
+        self.mined.push((block_name, self.nodes.len(), clock));
+
+        if release {
+            self.release();
+        }
+    }
+
+    /// The attacker publishes all its withheld blocks to every honest node, in mining order.
+    /// The honest blue blocks are recorded firstly, for the attack report.
+    ///
+    fn release(&mut self){
+
+        let honest_blue: Vec<HashSet<String>> = self.nodes.iter().map(|node| {
+            node.read().unwrap().dag.values().filter_map(|block| {
+                let block = block.read().unwrap();
+                if block.is_blue { Some(block.name.clone()) } else { None }
+            }).collect()
+        }).collect();
+
+        let withheld = {
+            let attacker = self.attacker.as_mut().unwrap();
+            let own: HashSet<&String> = attacker.blocks.iter().collect();
+            attacker.honest_blue_at_release = honest_blue.into_iter()
+                .map(|blues| blues.into_iter().filter(|name| !own.contains(name) && name != "Genesis").collect())
+                .collect();
+            attacker.released_at = Some(self.clock);
+            ::std::mem::take(&mut attacker.withheld)
+        };
+
+        info!("simulator: clock={}ms. attacker releases {} blocks", self.clock, withheld.len());
+
+        // one delay per node, so that the blocks arrive in mining order.
+        let from = self.nodes.len();
+        for to in 0..self.nodes.len() {
+            let delay = self.config.latency.sample(&mut self.rng);
+            for block in &withheld {
+                self.transmit(from, to, delay, Message::Block(block.clone()));
+            }
+        }
+    }
+
+    /// End of the network split. The colours are recorded for the partition report, then the nodes get what they missed
+    /// if the blocks across the cut were dropped.
+    ///
+    fn heal(&mut self){
+
+        {
+            let guards = self.nodes.iter().map(|n| n.read().unwrap()).collect::<Vec<_>>();
+            let nodes = guards.iter().map(|g| &**g).collect::<Vec<&Node>>();
+            self.partition.as_mut().unwrap().heal(&nodes, &self.mined, self.clock);
+        }
+        info!("simulator: clock={}ms. network split healed", self.clock);
+
+        let (side_a, cut) = {
+            let config = &self.partition.as_ref().unwrap().config;
+            (config.side_a, config.cut.clone())
+        };
+
+        // a simple resync standing for a sync protocol: each node sends its peers across the cut the blocks they don't have,
+        // in its adding order. a block is sent only once to a node.
+        if cut == PartitionCut::Drop {
+            let mut sent: Vec<HashSet<String>> = vec![HashSet::new(); self.nodes.len()];
+            for from in 0..self.nodes.len() {
+                let blocks: Vec<BlockRaw> = {
+                    let node_r = self.nodes[from].read().unwrap();
+                    let mut sorted: Vec<(u64, BlockRaw)> = node_r.dag.values().map(|block| {
+                        let block = block.read().unwrap();
+                        (block.sequence, block.to_raw())
+                    }).collect();
+                    sorted.sort_by_key(|(sequence,_)| *sequence);
+                    sorted.into_iter().map(|(_,raw)| raw).collect()
+                };
+
+                for i in 0..self.links[from].len() {
+                    let to = self.links[from][i].to;
+                    if (from < side_a) == (to < side_a) {
+                        continue;
+                    }
+                    let missing: Vec<BlockRaw> = {
+                        let node_r = self.nodes[to].read().unwrap();
+                        blocks.iter().filter(|raw| !node_r.dag.contains_key(&raw.name) && !sent[to].contains(&raw.name)).cloned().collect()
+                    };
+                    let delay = self.links[from][i].latency.sample(&mut self.rng);
+                    for raw in missing {
+                        sent[to].insert(raw.name.clone());
+                        self.transmit(from, to, delay, Message::Block(raw));
+                    }
+                }
+            }
+        }
+
+        self.schedule(RECONCILE_CHECK_INTERVAL, EventKind::Check);
+    }
+
+    /// Send a block to all the peers of a node, except the one it came from.
+    ///
+    fn send(&mut self, from: usize, except: Option<usize>, block: &BlockRaw){
+
+        for i in 0..self.links[from].len() {
+            let to = self.links[from][i].to;
+            if Some(to) == except {
+                continue;
+            }
+            let delay = self.links[from][i].latency.sample(&mut self.rng);
+            self.transmit(from, to, delay, Message::Block(block.clone()));
+        }
+    }
+
+    /// Announce blocks to all the peers of a node which don't know them yet, except the one they came from.
+    ///
+    fn announce(&mut self, from: usize, except: Option<usize>, names: &[String]){
+
+        let tracks_inventory = self.tracks_inventory();
+        for i in 0..self.links[from].len() {
+            let to = self.links[from][i].to;
+            if Some(to) == except {
+                continue;
+            }
+            let names = if tracks_inventory {
+                self.inventories[from].entry(to).or_default().announce(names)
+            }else{
+                names.to_vec()
+            };
+            if names.is_empty() {
+                continue;
+            }
+            let delay = self.links[from][i].latency.sample(&mut self.rng);
+            self.transmit(from, to, delay, Message::Inv(names));
+        }
+    }
+
+    /// Schedule the delivery of a message over a link, unless it's dropped by a network split.
+    ///
+    fn transmit(&mut self, from: usize, to: usize, delay: u64, message: Message){
+
+        let mut delay = delay;
+        if let Some(ref partition) = self.partition {
+            if partition.config.is_cut(from, to, self.nodes.len(), self.clock) {
+                match partition.config.cut {
+                    PartitionCut::Drop => return,
+                    PartitionCut::Delay => delay += partition.config.heal_time() - self.clock,
+                }
+            }
+        }
+
+        self.traffic.messages += 1;
+        self.traffic.bytes += message.encoded_size() as u64;
+        if let Message::Block(_) = message {
+            self.traffic.block_transfers += 1;
+        }
+        if self.tracks_inventory() && from < self.nodes.len() {
+            mark_known(&mut self.inventories[from], to, &message);
+        }
+
+        self.schedule(delay, EventKind::Deliver{from, to, message: Box::new(message)});
+    }
+
+    fn tracks_inventory(&self) -> bool{
+        match self.config.topology {
+            Topology::FullMesh => false,
+            _ => self.config.relay == Relay::Inventory,
+        }
+    }
+
+    fn schedule(&mut self, delay: u64, kind: EventKind){
+        self.seq += 1;
+        self.events.push(Event{time: self.clock + delay, seq: self.seq, kind});
+    }
+
+    /// Start a new mining round of the node: the time to find a block on its current template, at its hash rate.
+    ///
+    fn schedule_find(&mut self, miner: usize){
+
+        if self.mined.len() as u64 >= self.config.blocks {
+            return;
+        }
+
+        // the attacker mines on its own tips until the release, with its share of the total hash rate.
+        let pow = self.config.pow.as_ref().unwrap();
+        let (target, hash_rate) = if miner == self.nodes.len() {
+            let attacker = match self.attacker {
+                Some(ref attacker) if attacker.is_active() => attacker,
+                _ => return,
+            };
+            let node = attacker.node.read().unwrap();
+            let parents = TopScore{n: (self.config.k+1) as usize}.select(&node);
+            let fraction = attacker.config.hash_fraction.min(0.99);
+            let honest: f64 = (0..self.nodes.len()).map(|number| pow.hash_rates.get(number).cloned().unwrap_or(1.0)).sum();
+            (next_target(&node, &parents, node.daa.as_ref().unwrap()), honest * fraction / (1.0 - fraction))
+        }else{
+            let node = self.nodes[miner].read().unwrap();
+            let parents = self.selectors[miner].select(&node);
+            (next_target(&node, &parents, node.daa.as_ref().unwrap()), pow.hash_rates.get(miner).cloned().unwrap_or(1.0))
+        };
+        let delay = exponential(&mut self.rng, expected_hashes(target) / hash_rate).round() as u64;
+
+        self.rounds[miner] += 1;
+        let round = self.rounds[miner];
+        self.schedule(delay, EventKind::Find{miner, round});
+    }
+
+    fn next_block_delay(&mut self) -> u64{
+        let interval = self.config.block_interval as f64;
+        exponential(&mut self.rng, interval).round() as u64
+    }
+}
+
+/// Mine a block on the tips picked by the selector. With proof of work, the header is solved from a random nonce.
+///
+fn mine_on(node: &mut Node, name: &str, selector: &mut dyn ParentSelector, k: i32, pow: bool, rng: &mut XorShiftRng) -> Result<BlockRaw, BlockError>{
+
+    if !pow {
+        return node.mine_block(name, selector, k);
+    }
+
+    let mut header = node.build_block_template(selector).header(name);
+    header.nonce = rng.gen();
+    solve(&mut header, u64::MAX);
+    if let Some(ref key) = node.miner_key {
+        key.sign(&mut header);
+    }
+    node.submit_block(&header, k)?;
+    let block = node.dag.get(name).unwrap().read().unwrap();
+    Ok(block.to_raw())
+}
+
+/// A block sent or announced to a peer, or received or announced from it, is known by this peer.
+///
+fn mark_known(inventories: &mut HashMap<usize, PeerInventory>, peer: usize, message: &Message){
+    match *message {
+        Message::Block(ref block) => inventories.entry(peer).or_default().mark_known(&block.name),
+        Message::Inv(ref names) => {
+            let inventory = inventories.entry(peer).or_default();
+            for name in names {
+                inventory.mark_known(name);
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Sample of an exponential distribution with the given mean.
+///
+fn exponential<R: Rng>(rng: &mut R, mean: f64) -> f64{
+    let uniform: f64 = 1.0 - rng.gen::<f64>();              // in (0,1], to avoid ln(0).
+    -uniform.ln() * mean
+}
+
+/// The miner key of a node, derived from the simulation seed and the node number.
+///
+fn miner_key(seed: u64, number: usize) -> MinerKey{
+    let mut bytes = [0x5au8; 32];
+    bytes[..8].copy_from_slice(&seed.to_be_bytes());
+    bytes[8..16].copy_from_slice(&(number as u64).to_be_bytes());
+    MinerKey::from_seed(&bytes)
+}
@@ -17,10 +17,15 @@
 
 use std::collections::HashMap;
 use std::sync::{Arc,RwLock};
+use std::sync::mpsc::Sender;
 use std::fmt;
 
-use blockdag::{Block,BlockRaw};
-use blockdag::{dag_add_block,sorted_keys_by_height,calc_blue};
+use blockdag::{Block,BlockRaw,BlockError,ParentSelector,DaaConfig,TimeRules};
+use blockdag::{MinerKey,VirtualBlock,ChainChanged,update_selected_chain};
+use blockdag::{dag_add_block,sorted_keys_by_height};
+use blockdag::{ColouringEngine,ColouringError,CalcBlue};
+
+pub const MAX_COLOUR_LOG: usize = 100_000;                  // default entries kept in the colour log, see 'Node::log_colours'.
 
 /// Structure providing fast access to node data.
 ///
@@ -33,6 +38,18 @@ pub struct Node{
     pub classmates: HashMap<u64, Vec<String>>,
     pub hourglass: Vec<(u64,u64)>,
     pub mined_blocks: u64,
+    pub daa: Option<DaaConfig>,                             // proof of work and difficulty adjustment, None for no proof of work.
+    pub time_rules: Option<TimeRules>,                      // None for no timestamp check.
+    pub clock: u64,                                         // ms, kept up to date by the owner of the node. the virtual time in simulation.
+    pub miner_key: Option<MinerKey>,                        // signs the mined blocks, None for anonymous blocks.
+    pub colour_log: Vec<String>,                            // the latest added blocks and recoloured ones, in order. see 'colour_log_since'.
+    pub colour_log_start: usize,                            // the position of the first entry kept in the colour log.
+    pub max_colour_log: usize,                              // beyond, the oldest entries of the colour log are dropped.
+    pub virtual_block: VirtualBlock,                        // over the current tips, see 'VirtualBlock'.
+    pub chain: Vec<String>,                                 // the selected parent chain, from Genesis. see 'selected_chain'.
+    pub chain_index: HashMap<String, usize>,                // block -> its index in the chain.
+    pub rescored: Vec<String>,                              // blocks whose 'size_of_past_blue' was rewritten since the last chain update.
+    pub chain_subscribers: Vec<Sender<ChainChanged>>,
 }
 
 impl Node {
@@ -47,10 +64,65 @@ impl Node {
             classmates: HashMap::new(),
             hourglass: Vec::new(),
             mined_blocks: 0 as u64,
+            daa: None,
+            time_rules: None,
+            clock: 0,
+            miner_key: None,
+            colour_log: Vec::new(),
+            colour_log_start: 0,
+            max_colour_log: MAX_COLOUR_LOG,
+            virtual_block: VirtualBlock::default(),
+            chain: Vec::new(),
+            chain_index: HashMap::new(),
+            rescored: Vec::new(),
+            chain_subscribers: Vec::new(),
         }));
 
         return node;
     }
+
+    /// Mine a new block on the tips picked by the selector. Return the new block, or the error of 'submit_block',
+    /// e.g. if the name is already used.
+    ///
+    pub fn mine_block(&mut self, name: &str, selector: &mut dyn ParentSelector, k: i32) -> Result<BlockRaw, BlockError>{
+
+        let mut header = self.build_block_template(selector).header(name);
+        if let Some(ref key) = self.miner_key {
+            key.sign(&mut header);
+        }
+        self.submit_block(&header, k)?;
+
+        let block = self.dag.get(name).unwrap().read().unwrap();
+        Ok(block.to_raw())
+    }
+
+    /// Record added or recoloured blocks in the colour log. Beyond 'max_colour_log' entries, the oldest half is dropped.
+    ///
+    pub(crate) fn log_colours<I>(&mut self, names: I) where I: IntoIterator<Item = String> {
+
+        self.colour_log.extend(names);
+        if self.colour_log.len() > self.max_colour_log {
+            let dropped = self.colour_log.len() - self.max_colour_log / 2;
+            self.colour_log.drain(..dropped);
+            self.colour_log_start += dropped;
+        }
+    }
+
+    /// The position after the last entry of the colour log, for the next 'colour_log_since'.
+    ///
+    pub fn colour_log_end(&self) -> usize{
+        self.colour_log_start + self.colour_log.len()
+    }
+
+    /// The entries of the colour log from a position kept by a consumer. None if some of them are already dropped:
+    /// then the consumer has to check all the blocks again.
+    ///
+    pub fn colour_log_since(&self, position: usize) -> Option<&[String]>{
+        if position < self.colour_log_start {
+            return None;
+        }
+        Some(&self.colour_log[(position - self.colour_log_start).min(self.colour_log.len())..])
+    }
 }
 
 impl fmt::Display for Node {
@@ -103,15 +175,30 @@ impl fmt::Display for Node {
 //    drop(propagations);
 //}
 
-pub fn handle_block_rx(block_rx: BlockRaw, node: &mut Node, stash: &mut HashMap<String, BlockRaw>, k: i32){
+/// Handle a received block. Return the names of all the blocks added into the dag by this call, in the adding order:
+/// the received block itself, and those stash blocks released by it.
+///
+pub fn handle_block_rx(block_rx: BlockRaw, node: &mut Node, stash: &mut HashMap<String, BlockRaw>, k: i32) -> Vec<String>{
+
+    let mut all_added: Vec<String> = Vec::new();
+
+    if node.dag.contains_key(&block_rx.name) {
+        return all_added; // block already received.
+    }
 
     stash.entry(block_rx.name.clone()).or_insert(block_rx);
 
     // local processing with stash
 
     let mut block_added: Vec<String> = Vec::new();
+    let mut block_rejected: Vec<String> = Vec::new();
     loop {
-        'outer: for (name_of_stash_block, stash_block) in &*stash {
+        // scan in topological order (size_of_past_set, then name), so the adding order doesn't depend on the hashmap iteration order.
+        let mut stash_keys = stash.iter().map(|(name,block)| (block.size_of_past_set, name.clone())).collect::<Vec<(u64,String)>>();
+        stash_keys.sort();
+
+        'outer: for (_, name_of_stash_block) in &stash_keys {
+            let stash_block = &stash[name_of_stash_block];
 
             // before adding to dag, make sure all its predecessors are already in dag, otherwise skip it for this time.
             for prev in &stash_block.prev {
@@ -121,22 +208,27 @@ pub fn handle_block_rx(block_rx: BlockRaw, node: &mut Node, stash: &mut HashMap<
                 }
             }
 
-            let prev_names = stash_block.prev.iter().map(|k| { &k[..] }).collect::<Vec<&str>>();
-            if true == node_add_block(name_of_stash_block, &prev_names, node, k, true) {
+            if true == node_add_raw(stash_block, node, k) {
                 block_added.push(name_of_stash_block.clone());
+            }else{
+                block_rejected.push(name_of_stash_block.clone());
             }
         }
 
-        for added_name in &block_added {
-            stash.remove(added_name);
+        // a rejected block would be rejected again, drop it too.
+        for name in block_added.iter().chain(block_rejected.iter()) {
+            stash.remove(name);
         }
+        block_rejected.clear();
 
         if block_added.len()==0 {
             break;
         }
+        all_added.append(&mut block_added);
         // in cast one released stash block could release another stash block, loop check.
-        block_added.truncate(0);
     }
+
+    all_added
 }
 
 pub fn node_add_block(name_of_new_block: &str, references: &Vec<&str>, node: &mut Node, k: i32, do_update_tips: bool) -> bool {
@@ -169,6 +261,7 @@ pub fn node_add_block(name_of_new_block: &str, references: &Vec<&str>, node: &mu
             //todo: limit the classmates size, only keep latest heights.
 
             node.size_of_dag += 1;
+            node.log_colours(Some(name_of_new_block.to_string()));
         }else{
             warn!("node_add_block(): block not found in dag. dag_add_block failed?");
             return false;
@@ -176,22 +269,77 @@ pub fn node_add_block(name_of_new_block: &str, references: &Vec<&str>, node: &mu
     }
 
     if do_update_tips {
+        // calc_blue doesn't fail.
+        let _ = colour_new_block(name_of_new_block, node, k, &mut CalcBlue);
+    }
 
-        // update tips
-        update_tips(name_of_new_block, node);
+    return true;
+}
 
-        // keep this tips in the block as the snapshot tips
-        {
-            let block = Arc::clone(&node.dag.get(name_of_new_block).unwrap());
-            let block_w = &mut block.write().unwrap();
-            block_w.tips_snapshot = node.tips.clone();
-        }
+/// Update the tips for a block just added into the dag, then colour it with the engine, rebuild the virtual block and
+/// the selected chain. The virtual block and the chain are updated even if the colouring fails.
+///
+pub(crate) fn colour_new_block(name_of_new_block: &str, node: &mut Node, k: i32, engine: &mut dyn ColouringEngine) -> Result<(), ColouringError>{
+
+    // update tips
+    update_tips(name_of_new_block, node);
 
-        // calculate blue
-        calc_blue(name_of_new_block, node, k);
+    // keep this tips in the block as the snapshot tips
+    {
+        let block = Arc::clone(&node.dag.get(name_of_new_block).unwrap());
+        let block_w = &mut block.write().unwrap();
+        block_w.tips_snapshot = node.tips.clone();
     }
 
-    return true;
+    // calculate blue
+    let result = engine.add_block(name_of_new_block, node, k);
+
+    node.virtual_block = VirtualBlock::from_tips(node);
+    update_selected_chain(node);
+    result
+}
+
+/// Add a block received or mined, with its header fields, once checked by 'Node::check_header'. All its parents must be in the dag.
+///
+pub fn node_add_raw(raw: &BlockRaw, node: &mut Node, k: i32) -> bool{
+
+    match node.check_header(raw).and_then(|_| node_insert_raw(raw, node, k)) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("node_add_raw(): node={}. {}", node.name, e);
+            false
+        },
+    }
+}
+
+/// Same as 'node_add_raw', for a block whose header was already checked, e.g. by 'Node::check_header'. Return why the
+/// block is refused, i.e. it's already in the dag or one of its parents is not.
+///
+pub(crate) fn node_insert_raw(raw: &BlockRaw, node: &mut Node, k: i32) -> Result<(), BlockError>{
+
+    if let Some(parent) = raw.prev.iter().find(|prev| !node.dag.contains_key(*prev)) {
+        return Err(BlockError::MissingParent{block: raw.name.clone(), parent: parent.clone()});
+    }
+    let references = raw.prev.iter().map(|prev| prev.as_str()).collect();
+    if !node_add_block(&raw.name, &references, node, k, false) {
+        return Err(BlockError::Duplicate(raw.name.clone()));
+    }
+
+    // the header and payload are set before the colouring, so the chain change subscribers see a complete block.
+    {
+        let mut block = node.dag.get(&raw.name).unwrap().write().unwrap();
+        block.timestamp = raw.timestamp;
+        block.target = raw.target;
+        block.nonce = raw.nonce;
+        block.merkle_root = raw.merkle_root;
+        block.miner = raw.miner;
+        block.signature = raw.signature.clone();
+        block.txs = raw.txs.clone();
+    }
+
+    // calc_blue doesn't fail.
+    let _ = colour_new_block(&raw.name, node, k, &mut CalcBlue);
+    Ok(())
 }
 
 pub fn update_tips(name_of_new_block: &str, node: &mut Node){
@@ -0,0 +1,85 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet};
+use std::sync::{Arc,RwLock};
+
+use blockdag::Block;
+
+/// Function providing the total order of the dag blocks, from the blue selection.
+///
+/// Blue blocks are visited in topological order (size_of_past_set, then name). Right before each blue block, all the blocks
+/// of its past not ordered yet are put in the same topological order. So a red block is ordered just before the first blue block
+/// which has it in its past. Red blocks not in the past of any blue block come last.
+///
+pub fn dag_order(dag: &HashMap<String, Arc<RwLock<Block>>>) -> Vec<String>{
+    dag_order_after(dag, |_| false)
+}
+
+/// Function providing the order of the blocks after an ordered prefix, see 'dag_order'. The prefix must end with a blue
+/// block, and no block of it, nor any blue block ordered before its end, may be added or recoloured since it was ordered:
+/// then the order of the whole dag starts with this prefix. 'in_prefix' tells whether a block is in the prefix.
+///
+pub(crate) fn dag_order_after<F>(dag: &HashMap<String, Arc<RwLock<Block>>>, in_prefix: F) -> Vec<String>
+    where F: Fn(&str) -> bool {
+
+    let mut sorted: Vec<(u64,String,bool)> = dag.values().filter_map(|value| {
+        let block = value.read().unwrap();
+        if in_prefix(&block.name) {
+            return None;
+        }
+        Some((block.size_of_past_set, block.name.clone(), block.is_blue))
+    }).collect();
+    sorted.sort();
+
+    let mut order: Vec<String> = Vec::with_capacity(sorted.len());
+    let mut ordered: HashSet<String> = HashSet::with_capacity(sorted.len());
+
+    for (_, name, is_blue) in &sorted {
+        if !is_blue || ordered.contains(name) {
+            continue;
+        }
+
+        // the ordered set, with the prefix, is always past-closed, so the walk stops at the first ordered block.
+        let mut pending: Vec<(u64,String)> = Vec::new();
+        let mut visited: HashSet<String> = HashSet::new();
+        let mut stack: Vec<Arc<RwLock<Block>>> = dag.get(name).unwrap().read().unwrap().prev.values().cloned().collect();
+        while let Some(value) = stack.pop() {
+            let block = value.read().unwrap();
+            if ordered.contains(&block.name) || in_prefix(&block.name) || !visited.insert(block.name.clone()) {
+                continue;
+            }
+            pending.push((block.size_of_past_set, block.name.clone()));
+            stack.extend(block.prev.values().cloned());
+        }
+        pending.sort();
+
+        for (_, past_name) in pending {
+            ordered.insert(past_name.clone());
+            order.push(past_name);
+        }
+        ordered.insert(name.clone());
+        order.push(name.clone());
+    }
+
+    for (_, name, _) in sorted {
+        if !ordered.contains(&name) {
+            order.push(name);
+        }
+    }
+
+    order
+}
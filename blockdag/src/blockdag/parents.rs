@@ -0,0 +1,153 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use rand::{Rng,SeedableRng,XorShiftRng};
+
+use blockdag::{Node,get_stpq};
+
+/// How a miner picks the references (parents) of its new block among its tips.
+/// The selected tips are returned in score order (see 'get_stpq'), the best one firstly. Never empty when the node has tips.
+///
+pub trait ParentSelector{
+    fn select(&mut self, node: &Node) -> Vec<String>;
+}
+
+/// All the tips.
+///
+pub struct AllTips;
+
+impl ParentSelector for AllTips {
+
+    fn select(&mut self, node: &Node) -> Vec<String>{
+        get_stpq(&node.tips).into_iter().map(|(name,_,_)| name).collect()
+    }
+}
+
+/// The 'n' best tips by score. With n = K+1, it's the original rule of the miners.
+///
+pub struct TopScore{
+    pub n: usize,
+}
+
+impl ParentSelector for TopScore {
+
+    fn select(&mut self, node: &Node) -> Vec<String>{
+        get_stpq(&node.tips).into_iter().take(self.n.max(1)).map(|(name,_,_)| name).collect()
+    }
+}
+
+/// The blue tips only, or the best tip if none of them is blue.
+///
+pub struct BlueTips;
+
+impl ParentSelector for BlueTips {
+
+    fn select(&mut self, node: &Node) -> Vec<String>{
+
+        let score_stpq = get_stpq(&node.tips);
+        let mut selected: Vec<String> = score_stpq.iter()
+            .filter(|(name,_,_)| node.tips.get(name).is_some_and(|tip| tip.read().unwrap().is_blue))
+            .map(|(name,_,_)| name.clone()).collect();
+
+        if selected.is_empty() {
+            selected.extend(score_stpq.into_iter().take(1).map(|(name,_,_)| name));
+        }
+        selected
+    }
+}
+
+/// The best tip, plus 'n'-1 other tips picked at random.
+///
+pub struct RandomSubset{
+    pub n: usize,
+    rng: XorShiftRng,
+}
+
+impl RandomSubset {
+
+    pub fn new(n: usize, seed: u64) -> RandomSubset{
+        let seed = [seed as u32 ^ 0x5f37_59df, (seed >> 32) as u32 ^ 0x2c1b_3c6d, 0x297a_2d39, 0x7ed5_5d16];
+        RandomSubset{n, rng: XorShiftRng::from_seed(seed)}
+    }
+}
+
+impl ParentSelector for RandomSubset {
+
+    fn select(&mut self, node: &Node) -> Vec<String>{
+
+        let score_stpq = get_stpq(&node.tips);
+        if score_stpq.is_empty() {
+            return Vec::new();
+        }
+
+        // partial fisher-yates over the other tips, then restore the score order.
+        let mut others: Vec<usize> = (1..score_stpq.len()).collect();
+        let picks = self.n.max(1).min(score_stpq.len()) - 1;
+        for i in 0..picks {
+            let j = self.rng.gen_range(i, others.len());
+            others.swap(i, j);
+        }
+        others.truncate(picks);
+        others.sort();
+
+        ::std::iter::once(0).chain(others).map(|index| score_stpq[index].0.clone()).collect()
+    }
+}
+
+/// Cap on the references of another selector: its 'max' first ones, i.e. the best by score.
+///
+pub struct MaxParents{
+    pub inner: Box<dyn ParentSelector>,
+    pub max: usize,
+}
+
+impl ParentSelector for MaxParents {
+
+    fn select(&mut self, node: &Node) -> Vec<String>{
+        let mut selected = self.inner.select(node);
+        selected.truncate(self.max.max(1));
+        selected
+    }
+}
+
+/// Parent selection of the miners in a simulation, a 'ParentSelector' is built from it for each node.
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ParentStrategy{
+    #[default]
+    KPlusOne,                                               // the K+1 best tips by score.
+    TopScore(usize),
+    AllTips,
+    BlueTips,
+    RandomSubset(usize),
+    MaxParents(Box<ParentStrategy>, usize),
+}
+
+impl ParentStrategy {
+
+    /// Build the selector. 'seed' is for the random strategies.
+    ///
+    pub fn selector(&self, k: i32, seed: u64) -> Box<dyn ParentSelector>{
+        match *self {
+            ParentStrategy::KPlusOne => Box::new(TopScore{n: (k+1) as usize}),
+            ParentStrategy::TopScore(n) => Box::new(TopScore{n}),
+            ParentStrategy::AllTips => Box::new(AllTips),
+            ParentStrategy::BlueTips => Box::new(BlueTips),
+            ParentStrategy::RandomSubset(n) => Box::new(RandomSubset::new(n, seed)),
+            ParentStrategy::MaxParents(ref inner, max) => Box::new(MaxParents{inner: inner.selector(k, seed), max}),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use blockdag::{Node,Simulator};
+
+/// What happens to a block sent across the cut while the network is split.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub enum PartitionCut{
+    Delay,                                                  // held until the heal, then sent with the link latency.
+    Drop,                                                   // lost. at the heal, nodes send their peers across the cut the blocks they miss.
+}
+
+#[derive(Clone, Debug)]
+pub struct PartitionConfig{
+    pub start: u64,                                         // virtual time (ms) of the split.
+    pub duration: u64,                                      // ms until the heal.
+    pub side_a: usize,                                      // nodes 0..side_a are on one side of the cut, the others on the other side.
+    pub cut: PartitionCut,
+}
+
+impl PartitionConfig {
+
+    /// Split the network into two halves.
+    ///
+    pub fn halves(nodes: usize, start: u64, duration: u64, cut: PartitionCut) -> PartitionConfig{
+        PartitionConfig{start, duration, side_a: nodes / 2, cut}
+    }
+
+    pub fn heal_time(&self) -> u64{
+        self.start + self.duration
+    }
+
+    /// Whether a block sent from one node to another at this time crosses the cut.
+    /// Only the honest nodes are split, an attacker (index >= nodes) is never cut.
+    ///
+    pub fn is_cut(&self, from: usize, to: usize, nodes: usize, clock: u64) -> bool{
+        clock >= self.start && clock < self.heal_time() && from < nodes && to < nodes && (from < self.side_a) != (to < self.side_a)
+    }
+}
+
+/// State of a network split during a simulation.
+///
+pub struct Partition{
+    pub config: PartitionConfig,
+    pub healed_at: Option<u64>,
+    pub blocks_before_heal: Vec<String>,                    // blocks mined before the heal.
+    pub colours_at_heal: Vec<HashMap<String,bool>>,         // for each node, the colour of its blocks at the heal.
+    pub synced_at: Option<u64>,                             // first check time when all nodes have all the blocks mined before the heal.
+    pub reconciled_at: Option<u64>,                         // first check time when they also agree on the colours of these blocks.
+}
+
+impl Partition {
+
+    pub fn new(config: PartitionConfig) -> Partition{
+        Partition{
+            config,
+            healed_at: None,
+            blocks_before_heal: Vec::new(),
+            colours_at_heal: Vec::new(),
+            synced_at: None,
+            reconciled_at: None,
+        }
+    }
+
+    /// Record the blocks and colours of all the nodes at the heal.
+    ///
+    pub fn heal(&mut self, nodes: &[&Node], mined: &[(String, usize, u64)], clock: u64){
+
+        self.healed_at = Some(clock);
+        self.blocks_before_heal = mined.iter().map(|(name,_,_)| name.clone()).collect();
+        self.colours_at_heal = nodes.iter().map(|node| {
+            node.dag.iter().map(|(name, block)| (name.clone(), block.read().unwrap().is_blue)).collect()
+        }).collect();
+    }
+
+    /// Update 'synced_at' and 'reconciled_at'. Return true when both are done, i.e. no more check is needed.
+    ///
+    pub fn check(&mut self, nodes: &[&Node], clock: u64) -> bool{
+
+        if self.synced_at.is_none() && self.blocks_before_heal.iter().all(|name| nodes.iter().all(|node| node.dag.contains_key(name))) {
+            self.synced_at = Some(clock);
+        }
+        if self.synced_at.is_some() && self.reconciled_at.is_none() && self.colour_disagreements(nodes) == 0 {
+            self.reconciled_at = Some(clock);
+        }
+
+        self.reconciled_at.is_some()
+    }
+
+    /// Number of blocks mined before the heal, with not the same colour on all the nodes which have it.
+    ///
+    pub fn colour_disagreements(&self, nodes: &[&Node]) -> u64{
+
+        self.blocks_before_heal.iter().filter(|name| {
+            let mut colours = nodes.iter().filter_map(|node| node.dag.get(*name).map(|block| block.read().unwrap().is_blue));
+            match colours.next() {
+                Some(colour) => colours.any(|other| other != colour),
+                None => false,
+            }
+        }).count() as u64
+    }
+}
+
+/// Outcome of a network split, colour flips are summed over all the nodes.
+///
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PartitionReport{
+    pub start: u64,
+    pub healed_at: Option<u64>,
+    pub blocks_before_heal: u64,
+    pub flipped_to_red: u64,                                // (node, block) pairs blue at the heal, red at the end.
+    pub flipped_to_blue: u64,                               // (node, block) pairs red at the heal, blue at the end.
+    pub time_to_sync: Option<u64>,                          // ms from the heal to all nodes having all the blocks mined before the heal.
+    pub time_to_reconcile: Option<u64>,                     // ms from the heal to all nodes agreeing on the colours of these blocks.
+    pub colour_disagreements: u64,                          // blocks mined before the heal, with different colours among the nodes at the end.
+}
+
+impl fmt::Display for PartitionReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = |time: Option<u64>| time.map_or(String::from("none"), |t| format!("{}ms", t));
+        write!(f, "partition: start={}ms,healed_at={},blocks_before_heal={},flipped_to_red={},flipped_to_blue={},time_to_sync={},time_to_reconcile={},colour_disagreements={}",
+               self.start, ms(self.healed_at), self.blocks_before_heal, self.flipped_to_red, self.flipped_to_blue,
+               ms(self.time_to_sync), ms(self.time_to_reconcile), self.colour_disagreements)
+    }
+}
+
+/// Function providing the partition report of a finished simulation, or None if the simulation has no network split.
+///
+pub fn sim_partition_report(sim: &Simulator) -> Option<PartitionReport>{
+
+    let partition = sim.partition.as_ref()?;
+
+    let guards = sim.nodes.iter().map(|n| n.read().unwrap()).collect::<Vec<_>>();
+    let nodes = guards.iter().map(|g| &**g).collect::<Vec<&Node>>();
+
+    let mut flipped_to_red: u64 = 0;
+    let mut flipped_to_blue: u64 = 0;
+
+    for (node, colours) in nodes.iter().zip(partition.colours_at_heal.iter()) {
+        for (name, &was_blue) in colours {
+            let is_blue = node.dag.get(name).unwrap().read().unwrap().is_blue;
+            if was_blue && !is_blue {
+                flipped_to_red += 1;
+            }else if !was_blue && is_blue {
+                flipped_to_blue += 1;
+            }
+        }
+    }
+
+    Some(PartitionReport{
+        start: partition.config.start,
+        healed_at: partition.healed_at,
+        blocks_before_heal: partition.blocks_before_heal.len() as u64,
+        flipped_to_red,
+        flipped_to_blue,
+        time_to_sync: partition.healed_at.and_then(|healed| partition.synced_at.map(|synced| synced - healed)),
+        time_to_reconcile: partition.healed_at.and_then(|healed| partition.reconciled_at.map(|reconciled| reconciled - healed)),
+        colour_disagreements: partition.colour_disagreements(&nodes),
+    })
+}
@@ -0,0 +1,144 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use sha2::{Sha256,Digest};
+
+use blockdag::{Node,BlockRaw,past_blue_window};
+
+/// Difficulty adjustment over the dag.
+///
+/// The target of a new block is the average target of the last 'window' blue blocks of its past, scaled by how long
+/// these blocks took compared with 'block_interval'. Only blue blocks count, so that withheld or late (red) blocks don't
+/// pull the difficulty. The colours are the ones of the local node, which may differ among nodes, so a received target
+/// is accepted within a factor 'tolerance' of the locally computed one.
+///
+#[derive(Clone, Debug)]
+pub struct DaaConfig{
+    pub block_interval: u64,                                // targeted ms between two blue blocks.
+    pub window: usize,                                      // blue blocks of the past used for the adjustment.
+    pub initial_target: u64,                                // target while the past has less than 2 blue blocks with a target, i.e. mined with proof of work.
+    pub max_adjustment: u64,                                // the target moves by this factor at most, from the window average.
+    pub tolerance: u64,
+}
+
+impl Default for DaaConfig {
+
+    fn default() -> DaaConfig {
+        DaaConfig{
+            block_interval: 250,
+            window: 64,
+            initial_target: target_for(1_000.0),
+            max_adjustment: 4,
+            tolerance: 2,
+        }
+    }
+}
+
+/// The sha256 of all the fields of a header but the signature.
+/// The references are hashed sorted by name, their order in the header doesn't matter. The transactions are committed
+/// to through the merkle root.
+///
+pub fn header_digest(header: &BlockRaw) -> [u8; 32]{
+
+    let mut prev: Vec<&String> = header.prev.iter().collect();
+    prev.sort();
+
+    let mut hasher = Sha256::new();
+    hasher.update(header.name.as_bytes());
+    for prev in prev {
+        hasher.update([0u8]);
+        hasher.update(prev.as_bytes());
+    }
+    for field in &[header.height, header.size_of_past_set, header.timestamp, header.target, header.nonce] {
+        hasher.update(field.to_be_bytes());
+    }
+    hasher.update(header.merkle_root);
+    if let Some(ref miner) = header.miner {
+        hasher.update(miner);
+    }
+
+    hasher.finalize().into()
+}
+
+/// The proof of work hash of a header: the first 8 bytes of 'header_digest', as a big endian integer.
+///
+pub fn header_hash(header: &BlockRaw) -> u64{
+
+    let digest = header_digest(header);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Whether the header hash doesn't exceed its target. A header without target (0) has no proof of work.
+///
+pub fn check_pow(header: &BlockRaw) -> bool{
+    header.target > 0 && header_hash(header) <= header.target
+}
+
+/// Try the nonces from 'header.nonce' on, at most 'max_tries' of them. Return true when one satisfies the target.
+///
+pub fn solve(header: &mut BlockRaw, max_tries: u64) -> bool{
+
+    for _ in 0..max_tries {
+        if check_pow(header) {
+            return true;
+        }
+        header.nonce = header.nonce.wrapping_add(1);
+    }
+    false
+}
+
+/// The target for which a block needs 'hashes' hashes in average.
+///
+pub fn target_for(hashes: f64) -> u64{
+    (u64::MAX as f64 / hashes.max(1.0)) as u64
+}
+
+/// The average number of hashes to find a block with this target.
+///
+pub fn expected_hashes(target: u64) -> f64{
+    u64::MAX as f64 / target.max(1) as f64
+}
+
+/// Function providing the target of a new block with these parents, see 'DaaConfig'.
+///
+pub fn next_target(node: &Node, parents: &[String], config: &DaaConfig) -> u64{
+
+    let window = past_blue_window(node, parents, config.window, |block| block.target > 0);
+
+    if window.len() < 2 {
+        return config.initial_target;
+    }
+
+    let first = window.iter().map(|&(timestamp,_)| timestamp).min().unwrap();
+    let last = window.iter().map(|&(timestamp,_)| timestamp).max().unwrap();
+    let timespan = (last - first).max(1) as u128;
+    let expected = (window.len() as u128 - 1) * config.block_interval.max(1) as u128;
+    let average = window.iter().map(|&(_,target)| target as u128).sum::<u128>() / window.len() as u128;
+
+    let max_adjustment = config.max_adjustment.max(1) as u128;
+    let target = (average * timespan / expected).clamp(average / max_adjustment, average * max_adjustment);
+    target.clamp(1, u64::MAX as u128) as u64
+}
+
+/// Whether a received target is close enough to the locally computed one, see 'DaaConfig'.
+///
+pub fn target_accepted(target: u64, computed: u64, config: &DaaConfig) -> bool{
+    let tolerance = config.tolerance.max(1) as u128;
+    let (target, computed) = (target as u128, computed as u128);
+    target * tolerance >= computed && target <= computed * tolerance
+}
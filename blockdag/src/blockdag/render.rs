@@ -0,0 +1,160 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Write;
+
+use blockdag::{Node,sorted_keys_by_height};
+
+const COLUMN_WIDTH: u64 = 110;
+const ROW_HEIGHT: u64 = 60;
+const MARGIN: u64 = 40;
+const BOX_WIDTH: u64 = 70;
+const BOX_HEIGHT: u64 = 30;
+
+const BLUE_FILL: &str = "#4a90d9";
+const RED_FILL: &str = "#d0021b";
+
+/// Function providing a standalone SVG picture of the node's dag, no Graphviz needed.
+///
+/// Blocks are laid out in columns by height, and inside one column by the order of the 'classmates' of that height.
+/// Each block shows its fields as a hover tooltip.
+///
+pub fn dag_render_svg(node: &Node) -> String{
+
+    let positions = layout(node);
+
+    let rows = positions.values().map(|&(_,row)| row+1).max().unwrap_or(1);
+    let width = (node.height+1) * COLUMN_WIDTH + 2*MARGIN;
+    let height = rows * ROW_HEIGHT + 2*MARGIN;
+
+    let mut svg = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"12\">\n",
+                          width, height, width, height);
+    svg.push_str("<defs><marker id=\"arrow\" viewBox=\"0 0 10 10\" refX=\"10\" refY=\"5\" markerWidth=\"6\" markerHeight=\"6\" orient=\"auto\">");
+    svg.push_str("<path d=\"M 0 0 L 10 5 L 0 10 z\" fill=\"#888\"/></marker></defs>\n");
+
+    let sorted_keys = sorted_keys_by_height(&node.dag, false);
+
+    // edges firstly, so that the blocks are drawn on top of them.
+    svg.push_str("<g stroke=\"#888\" stroke-width=\"1.2\" fill=\"none\">\n");
+    for (name,_) in &sorted_keys {
+        let block = node.dag.get(name).unwrap().read().unwrap();
+        let (x1,y1) = box_center(positions[name]);
+
+        for (prev,_) in sorted_keys_by_height(&block.prev, false) {
+            if let Some(&position) = positions.get(&prev) {
+                let (x2,y2) = box_center(position);
+                svg.push_str(&format!("<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" marker-end=\"url(#arrow)\"/>\n",
+                                      x1 - BOX_WIDTH/2, y1, x2 + BOX_WIDTH/2, y2));
+            }
+        }
+    }
+    svg.push_str("</g>\n");
+
+    for (name,_) in &sorted_keys {
+        let block = node.dag.get(name).unwrap().read().unwrap();
+        let (x,y) = box_center(positions[name]);
+        let fill = if block.is_blue { BLUE_FILL } else { RED_FILL };
+
+        let prev_names = sorted_keys_by_height(&block.prev, false).iter().map(|(n,_)| n.clone()).collect::<Vec<String>>();
+        let tooltip = format!("name={}\nheight={}\nsize_of_past_set={}\nsize_of_past_blue={}\nis_blue={}\nsize_of_anticone_blue={}\nprev=[{}]",
+                              block.name, block.height, block.size_of_past_set, block.size_of_past_blue,
+                              block.is_blue, block.size_of_anticone_blue, prev_names.join(","));
+
+        svg.push_str(&format!("<g class=\"block\"><title>{}</title>", escape_xml(&tooltip)));
+        svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"6\" fill=\"{}\"/>",
+                              x - BOX_WIDTH/2, y - BOX_HEIGHT/2, BOX_WIDTH, BOX_HEIGHT, fill));
+        svg.push_str(&format!("<text x=\"{}\" y=\"{}\" fill=\"#fff\" text-anchor=\"middle\" dominant-baseline=\"central\">{}</text></g>\n",
+                              x, y, escape_xml(&block.name)));
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Function providing a self-contained HTML page with the SVG picture of the node's dag embedded.
+///
+pub fn dag_render_html(node: &Node) -> String{
+
+    let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    html.push_str(&format!("<title>{}</title>\n", escape_xml(&node.name)));
+    html.push_str("<style>body{font-family:sans-serif;margin:16px;} .block:hover rect{stroke:#000;stroke-width:2;}</style>\n");
+    html.push_str("</head>\n<body>\n");
+    html.push_str(&format!("<h3>node={},height={},size_of_dag={},tips={}</h3>\n",
+                           escape_xml(&node.name), node.height, node.size_of_dag, node.tips.len()));
+    html.push_str(&dag_render_svg(node));
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Write the node's dag picture into a file. A '.html' or '.htm' file gets the HTML page, otherwise it's a plain SVG.
+///
+pub fn dag_render_to_file(node: &Node, path: &str) -> io::Result<()>{
+
+    let lower = path.to_lowercase();
+    let content = if lower.ends_with(".html") || lower.ends_with(".htm") {
+        dag_render_html(node)
+    }else{
+        dag_render_svg(node)
+    };
+
+    let mut file = File::create(path)?;
+    file.write_all(content.as_bytes())
+}
+
+/// Position of each block in (column,row), column is the height and row is the index among its classmates.
+///
+fn layout(node: &Node) -> HashMap<String,(u64,u64)>{
+
+    let mut positions: HashMap<String,(u64,u64)> = HashMap::new();
+    let mut extra_rows: HashMap<u64,u64> = HashMap::new();
+
+    for (name,height) in sorted_keys_by_height(&node.dag, false) {
+        let row = match node.classmates.get(&height) {
+            Some(classmates) => classmates.iter().position(|n| n == &name),
+            None => None,
+        };
+
+        // a block not found in classmates is put below all the classmates of the same height, each on its own row.
+        let row = match row {
+            Some(row) => row as u64,
+            None => {
+                let extra = extra_rows.entry(height).or_insert(0);
+                *extra += 1;
+                node.classmates.get(&height).map_or(0, |c| c.len() as u64) + *extra - 1
+            },
+        };
+
+        positions.insert(name, (height, row));
+    }
+
+    positions
+}
+
+fn box_center(position: (u64,u64)) -> (u64,u64){
+    (MARGIN + position.0 * COLUMN_WIDTH + BOX_WIDTH/2, MARGIN + position.1 * ROW_HEIGHT + BOX_HEIGHT/2)
+}
+
+fn escape_xml(s: &str) -> String{
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+        .replace('\n', "&#10;")
+}
@@ -0,0 +1,141 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet};
+
+use blockdag::{Node,Block};
+
+/// Rewards of the miners, from the colours of their blocks.
+///
+/// A blue block gets 'block_reward', a red one 'red_reward_percent' of it. A red block is merged by its first child in
+/// the order, i.e. the one with the smallest past, then by name, which gets 'merge_fee' for it: so that the miners
+/// still reference the red blocks they see.
+///
+#[derive(Clone, Debug)]
+pub struct RewardConfig{
+    pub block_reward: u64,
+    pub red_reward_percent: u64,
+    pub merge_fee: u64,                                     // per red parent merged.
+}
+
+impl Default for RewardConfig {
+
+    fn default() -> RewardConfig {
+        RewardConfig{
+            block_reward: 1000,
+            red_reward_percent: 0,
+            merge_fee: 100,
+        }
+    }
+}
+
+/// Rewards per block and per miner (public key), see 'RewardConfig'. Genesis has no reward, and the anonymous blocks
+/// don't count for any miner.
+///
+/// 'update' follows the colours through 'Node::colour_log': only the added or recoloured blocks, their children and the
+/// children of their parents (whose merger may change) are computed again.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Rewards{
+    pub config: RewardConfig,
+    pub blocks: HashMap<String, u64>,
+    pub miners: HashMap<[u8; 32], u64>,
+    position: usize,                                        // in the colour log of the node.
+}
+
+impl Rewards {
+
+    pub fn new(config: RewardConfig) -> Rewards{
+        Rewards{config, ..Rewards::default()}
+    }
+
+    /// The reward of a block with the current colours.
+    ///
+    pub fn block_reward(&self, block: &Block) -> u64{
+
+        if block.name == "Genesis" {
+            return 0;
+        }
+
+        let mut reward = if block.is_blue {
+            self.config.block_reward
+        } else {
+            self.config.block_reward * self.config.red_reward_percent / 100
+        };
+
+        for prev in block.prev.values() {
+            let prev = prev.read().unwrap();
+            if !prev.is_blue && first_child(&prev).as_ref() == Some(&block.name) {
+                reward += self.config.merge_fee;
+            }
+        }
+        reward
+    }
+
+    /// Follow the colours of the node since the last update. Return how many blocks are computed again.
+    ///
+    pub fn update(&mut self, node: &Node) -> usize{
+
+        let mut dirty: HashSet<String> = HashSet::new();
+        match node.colour_log_since(self.position) {
+            Some(log) => {
+                for name in log {
+                    let block = node.dag.get(name).unwrap().read().unwrap();
+                    dirty.insert(name.clone());
+                    dirty.extend(block.next.keys().cloned());
+                    for prev in block.prev.values() {
+                        dirty.extend(prev.read().unwrap().next.keys().cloned());
+                    }
+                }
+            },
+            None => dirty.extend(node.dag.keys().cloned()),
+        }
+        self.position = node.colour_log_end();
+
+        for name in &dirty {
+            let block = node.dag.get(name).unwrap().read().unwrap();
+            let reward = self.block_reward(&block);
+            let old = self.blocks.insert(name.clone(), reward).unwrap_or(0);
+            if let Some(miner) = block.miner {
+                let miner_reward = self.miners.entry(miner).or_insert(0);
+                *miner_reward = *miner_reward + reward - old;
+            }
+        }
+        dirty.len()
+    }
+
+    pub fn total(&self) -> u64{
+        self.blocks.values().sum()
+    }
+}
+
+/// The child merging a block, see 'RewardConfig'.
+///
+fn first_child(block: &Block) -> Option<String>{
+    block.next.values().map(|next| {
+        let next = next.read().unwrap();
+        (next.size_of_past_set, next.name.clone())
+    }).min().map(|(_, name)| name)
+}
+
+/// Function providing the rewards of all the blocks of a node, with its current colours.
+///
+pub fn rewards_from_node(node: &Node, config: RewardConfig) -> Rewards{
+
+    let mut rewards = Rewards::new(config);
+    rewards.update(node);
+    rewards
+}
@@ -0,0 +1,380 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap,HashSet,VecDeque,BinaryHeap};
+
+use blockdag::node::node_insert_raw;
+use blockdag::{Node,BlockRaw,Transaction,sorted_keys_by_height};
+
+pub const MAX_HEADERS: usize = 2_000;                       // headers of a 'Headers' answer at most, whatever the requested 'max'.
+
+/// Messages between peers.
+///
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Message{
+    Block(BlockRaw),
+    GetBlocks(Vec<String>),                                 // request blocks by name, i.e. the missing parents of a stashed block.
+    GetTips,
+    Tips(Vec<String>),
+    GetHeaders{from: Vec<String>, max: usize},              // request these blocks and their past, walking back, 'max' blocks at most (up to 'MAX_HEADERS').
+    Headers(Vec<BlockRaw>),                                 // in reverse arrival order on the peer: descending sequence.
+    Inv(Vec<String>),                                       // announce blocks by name.
+    GetData(Vec<String>),                                   // request announced blocks.
+}
+
+impl Message {
+
+    /// Approximate size of the JSON encoding, in bytes, for the bandwidth metrics.
+    ///
+    pub fn encoded_size(&self) -> usize{
+
+        let names_size = |names: &[String]| names.iter().map(|name| name.len() + 3).sum::<usize>() + 2;
+        let tx_size = |tx: &Transaction| 40 + tx.id.len() + tx.inputs.iter().map(|input| 20 + input.tx.len()).sum::<usize>()
+            + tx.outputs.iter().map(|output| 30 + output.owner.len()).sum::<usize>();
+        let block_size = |block: &BlockRaw| 96 + block.miner.map_or(0, |_| 96) + block.name.len() + names_size(&block.prev) + block.txs.iter().map(tx_size).sum::<usize>();
+
+        match *self {
+            Message::Block(ref block) => 12 + block_size(block),
+            Message::GetBlocks(ref names) | Message::Tips(ref names) | Message::Inv(ref names) | Message::GetData(ref names) => 14 + names_size(names),
+            Message::GetTips => 9,
+            Message::GetHeaders{ref from, ..} => 40 + names_size(from),
+            Message::Headers(ref blocks) => 14 + blocks.iter().map(|block| block_size(block) + 1).sum::<usize>(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StashConfig{
+    pub max_size: usize,                                    // blocks in the stash. the oldest ones are evicted firstly.
+    pub max_age: u64,                                       // ms a block can wait in the stash for its parents, before eviction.
+    pub request_timeout: u64,                               // ms before requesting again a missing parent.
+}
+
+impl Default for StashConfig {
+
+    fn default() -> StashConfig {
+        StashConfig{
+            max_size: 10_000,
+            max_age: 120_000,
+            request_timeout: 2_000,
+        }
+    }
+}
+
+struct StashEntry{
+    block: BlockRaw,
+    missing: usize,                                         // how many parents are not in the dag yet.
+}
+
+/// Blocks received before (some of) their parents, indexed by missing parent: when a block is added into the dag,
+/// only the stashed blocks waiting for it are checked.
+///
+pub struct BlockStash{
+    pub config: StashConfig,
+    pub evicted: u64,                                       // how many blocks were evicted, for too old or a full stash.
+    pub dropped: u64,                                       // how many blocks were dropped, for a rejected ancestor.
+    blocks: HashMap<String, StashEntry>,
+    children: HashMap<String, HashSet<String>>,             // missing parent -> stashed blocks waiting for it.
+    requested: HashMap<String, u64>,                        // missing parent -> time of the last request.
+    arrivals: VecDeque<(u64, String)>,                      // (arrival time, block) in arrival order. removed blocks are skipped lazily.
+    rejected: HashSet<String>,                              // blocks refused by the node and their descendants, never requested again.
+    rejections: VecDeque<String>,                           // the rejected blocks in order, the oldest ones are forgotten beyond 'max_size'.
+}
+
+impl BlockStash {
+
+    pub fn new(config: StashConfig) -> BlockStash{
+        BlockStash{
+            config,
+            evicted: 0,
+            dropped: 0,
+            blocks: HashMap::new(),
+            children: HashMap::new(),
+            requested: HashMap::new(),
+            arrivals: VecDeque::new(),
+            rejected: HashSet::new(),
+            rejections: VecDeque::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize{
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool{
+        self.blocks.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool{
+        self.blocks.contains_key(name)
+    }
+
+    /// Whether the block was refused by the node, or descends from such a block.
+    ///
+    pub fn is_rejected(&self, name: &str) -> bool{
+        self.rejected.contains(name)
+    }
+
+    /// The parents waited by stashed blocks, which are not in the stash either. Sorted by name.
+    ///
+    pub fn missing_parents(&self) -> Vec<String>{
+        let mut missing: Vec<String> = self.children.keys().filter(|name| !self.blocks.contains_key(*name)).cloned().collect();
+        missing.sort();
+        missing
+    }
+
+    fn insert(&mut self, block: BlockRaw, missing: Vec<String>, clock: u64){
+
+        for parent in &missing {
+            self.children.entry(parent.clone()).or_default().insert(block.name.clone());
+        }
+        self.requested.remove(&block.name);
+        self.arrivals.push_back((clock, block.name.clone()));
+        self.blocks.insert(block.name.clone(), StashEntry{block, missing: missing.len()});
+    }
+
+    fn remove(&mut self, name: &str) -> Option<BlockRaw>{
+
+        let entry = self.blocks.remove(name)?;
+        for parent in &entry.block.prev {
+            let empty = match self.children.get_mut(parent) {
+                Some(waiting) => {
+                    waiting.remove(name);
+                    waiting.is_empty()
+                },
+                None => false,
+            };
+            if empty {
+                self.children.remove(parent);
+                self.requested.remove(parent);
+            }
+        }
+        Some(entry.block)
+    }
+
+    /// Record a block refused by the node, and drop the stashed blocks descending from it, which can't be added either.
+    ///
+    fn reject(&mut self, name: &str){
+
+        let mut pending = vec![name.to_string()];
+        while let Some(name) = pending.pop() {
+            if self.rejected.insert(name.clone()) {
+                self.rejections.push_back(name.clone());
+            }
+            self.requested.remove(&name);
+            for child in self.children.remove(&name).unwrap_or_default() {
+                if self.remove(&child).is_some() {
+                    self.dropped += 1;
+                    debug!("stash: block {} dropped, its ancestor {} is rejected", child, name);
+                    pending.push(child);
+                }
+            }
+        }
+
+        while self.rejections.len() > self.config.max_size {
+            if let Some(name) = self.rejections.pop_front() {
+                self.rejected.remove(&name);
+            }
+        }
+    }
+
+    /// Evict the blocks older than 'max_age', then the oldest ones while the stash is larger than 'max_size'.
+    /// It runs on each handled message, so the stash expires even when it doesn't grow anymore.
+    ///
+    pub fn evict(&mut self, clock: u64){
+
+        while let Some((arrival, name)) = self.arrivals.front().cloned() {
+            if !self.blocks.contains_key(&name) {
+                self.arrivals.pop_front();
+                continue;
+            }
+            if clock.saturating_sub(arrival) <= self.config.max_age && self.blocks.len() <= self.config.max_size {
+                break;
+            }
+            self.arrivals.pop_front();
+            self.remove(&name);
+            self.evicted += 1;
+            debug!("stash: block {} evicted. arrival={}ms, clock={}ms, size={}", name, arrival, clock, self.blocks.len());
+        }
+    }
+
+    /// The missing parents to request now: not requested yet, or requested more than 'request_timeout' ago.
+    ///
+    fn request_due(&mut self, parents: &[String], clock: u64) -> Vec<String>{
+
+        let mut request: Vec<String> = Vec::new();
+        for parent in parents {
+            if self.blocks.contains_key(parent) || self.rejected.contains(parent) {
+                continue;                                   // stashed, it's waiting for its own parents. or never to be added.
+            }
+            let due = self.requested.get(parent).is_none_or(|&time| clock.saturating_sub(time) >= self.config.request_timeout);
+            if due {
+                self.requested.insert(parent.clone(), clock);
+                request.push(parent.clone());
+            }
+        }
+        request
+    }
+}
+
+/// Outcome of a handled message: the blocks added into the dag in adding order, and the messages to reply to the sender.
+///
+#[derive(Debug, Default)]
+pub struct SyncResult{
+    pub added: Vec<String>,
+    pub replies: Vec<Message>,
+}
+
+/// Function providing the handling of a message from a peer.
+///
+/// A received block with all its parents in the dag is added, then the stashed blocks waiting for it are released.
+/// Otherwise it's stashed, and its missing parents are requested from the sender with a 'GetBlocks'.
+/// A block refused by the node is recorded, and the stashed blocks descending from it are dropped.
+/// A 'GetBlocks' is answered with the requested blocks we have, in topological order.
+///
+pub fn handle_message(message: Message, node: &mut Node, stash: &mut BlockStash, k: i32, clock: u64) -> SyncResult{
+
+    let mut result = SyncResult::default();
+    stash.evict(clock);
+
+    match message {
+        Message::Block(block) => {
+            if node.dag.contains_key(&block.name) || stash.contains(&block.name) || stash.is_rejected(&block.name) {
+                return result;                              // block already received.
+            }
+            if let Some(parent) = block.prev.iter().find(|prev| stash.is_rejected(prev)) {
+                warn!("sync: node={}. block {} rejected: its parent {} is rejected", node.name, block.name, parent);
+                stash.reject(&block.name);
+                return result;
+            }
+
+            // each missing parent once, as the stash counts them down once per parent added.
+            let mut missing: Vec<String> = Vec::new();
+            for prev in &block.prev {
+                if !node.dag.contains_key(prev) && !missing.contains(prev) {
+                    missing.push(prev.clone());
+                }
+            }
+            if missing.is_empty() {
+                stash.requested.remove(&block.name);
+                add_and_release(block, node, stash, k, &mut result.added);
+            }else{
+                let request = stash.request_due(&missing, clock);
+                stash.insert(block, missing, clock);
+                stash.evict(clock);
+                if !request.is_empty() {
+                    result.replies.push(Message::GetBlocks(request));
+                }
+            }
+        },
+        Message::Tips(_) | Message::Headers(_) => {
+            debug!("handle_message(): node={}. unsolicited {:?} ignored, only a DagSync handles it.", node.name, message);
+        },
+        _ => result.replies = handle_request(&message, node),
+    }
+
+    result
+}
+
+/// Function providing the answers to a request, which only reads the dag.
+///
+pub fn handle_request(message: &Message, node: &Node) -> Vec<Message>{
+
+    match *message {
+        Message::GetBlocks(ref names) | Message::GetData(ref names) => {
+            let mut blocks: Vec<(u64, BlockRaw)> = names.iter().filter_map(|name| node.dag.get(name)).map(|block| {
+                let block = block.read().unwrap();
+                (block.size_of_past_set, block.to_raw())
+            }).collect();
+            blocks.sort_by(|a, b| (a.0, &a.1.name).cmp(&(b.0, &b.1.name)));
+            blocks.into_iter().map(|(_, raw)| Message::Block(raw)).collect()
+        },
+        Message::GetTips => {
+            let tips = sorted_keys_by_height(&node.tips, false).into_iter().map(|(name,_)| name).collect();
+            vec![Message::Tips(tips)]
+        },
+        Message::GetHeaders{ref from, max} => {
+            let max = max.min(MAX_HEADERS);
+            // walk back from the requested blocks, the latest arrived block firstly. a syncing node adding them in the reverse order
+            // follows the same arrival order, then gets the same colouring, as calc_blue depends on it.
+            let mut headers: Vec<BlockRaw> = Vec::new();
+            let mut visited: HashSet<String> = HashSet::new();
+            let mut heap: BinaryHeap<(u64, String)> = BinaryHeap::new();
+
+            for name in from {
+                if let Some(block) = node.dag.get(name) {
+                    heap.push((block.read().unwrap().sequence, name.clone()));
+                    visited.insert(name.clone());
+                }
+            }
+
+            while let Some((_, name)) = heap.pop() {
+                if headers.len() >= max {
+                    break;
+                }
+                let block = node.dag.get(&name).unwrap().read().unwrap();
+                for (prev_name, prev) in &block.prev {
+                    if visited.insert(prev_name.clone()) {
+                        heap.push((prev.read().unwrap().sequence, prev_name.clone()));
+                    }
+                }
+                headers.push(block.to_raw());
+            }
+            vec![Message::Headers(headers)]
+        },
+        _ => Vec::new(),
+    }
+}
+
+/// Add a block whose parents are all in the dag, then the stashed blocks it releases, recursively.
+///
+fn add_and_release(block: BlockRaw, node: &mut Node, stash: &mut BlockStash, k: i32, added: &mut Vec<String>){
+
+    let mut ready: VecDeque<BlockRaw> = VecDeque::new();
+    ready.push_back(block);
+
+    while let Some(block) = ready.pop_front() {
+        if let Err(e) = node.check_header(&block).and_then(|_| node_insert_raw(&block, node, k)) {
+            warn!("sync: node={}. block rejected: {}", node.name, e);
+            stash.reject(&block.name);
+            continue;
+        }
+        added.push(block.name.clone());
+
+        let waiting = match stash.children.remove(&block.name) {
+            Some(waiting) => waiting,
+            None => continue,
+        };
+        stash.requested.remove(&block.name);
+
+        // in topological order (size_of_past_set, then name), so the adding order doesn't depend on the hashset iteration order.
+        let mut released: Vec<(u64, String)> = Vec::new();
+        for child in waiting {
+            let entry = stash.blocks.get_mut(&child).unwrap();
+            entry.missing -= 1;
+            if entry.missing == 0 {
+                released.push((entry.block.size_of_past_set, child));
+            }
+        }
+        released.sort();
+
+        for (_, child) in released {
+            if let Some(raw) = stash.remove(&child) {
+                ready.push_back(raw);
+            }
+        }
+    }
+}
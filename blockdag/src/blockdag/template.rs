@@ -0,0 +1,185 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+use std::sync::{Arc,RwLock};
+
+use blockdag::{Node,Block,BlockRaw,BlockError,ParentSelector,Transaction};
+use blockdag::node::node_insert_raw;
+use blockdag::{sizeof_pastset,next_target,target_accepted,check_pow,median_time_past,check_timestamp};
+use blockdag::{EMPTY_ROOT,tx_merkle_root,check_merkle_root,check_signature};
+
+/// What a miner needs to create a new block on top of the current tips.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockTemplate{
+    pub parents: Vec<String>,                               // in score order, the best one firstly.
+    pub height: u64,
+    pub size_of_past_set: u64,
+    pub size_of_past_blue: u64,                             // score of the new block, with the current colours.
+    pub target: u64,                                        // 0 when the node has no proof of work.
+    pub timestamp: u64,                                     // the node clock, or just after the median time past if the clock is behind.
+    pub txs: Vec<Transaction>,                              // the payload, empty in the built template, see 'Mempool::fill_template'.
+    pub miner: Option<[u8; 32]>,                            // the public key of the node miner key, if any.
+}
+
+impl BlockTemplate {
+
+    /// The header of the block to mine. In simulation, the name stands for the hash of the header.
+    /// With proof of work, the miner then searches a nonce, see 'solve'. Then it signs the header, see 'MinerKey::sign'.
+    ///
+    pub fn header(&self, name: &str) -> BlockRaw{
+        BlockRaw{
+            name: name.to_string(),
+            height: self.height,
+            size_of_past_set: self.size_of_past_set,
+            prev: self.parents.clone(),
+            timestamp: self.timestamp,
+            target: self.target,
+            nonce: 0,
+            merkle_root: tx_merkle_root(&self.txs),
+            miner: self.miner,
+            signature: None,
+            txs: self.txs.clone(),
+        }
+    }
+}
+
+impl Node {
+
+    /// Build a block template on the tips picked by the selector.
+    ///
+    pub fn build_block_template(&self, selector: &mut dyn ParentSelector) -> BlockTemplate{
+
+        let parents = selector.select(self);
+        let candidate = candidate_block("", &parents, self).unwrap();
+        let target = self.daa.as_ref().map_or(0, |daa| next_target(self, &parents, daa));
+        let timestamp = match self.time_rules {
+            // after the median time past, but not beyond the future drift. if the median time past itself is beyond it,
+            // the block is refused as too old by 'check_header': the miner has to wait for its clock to catch up.
+            Some(ref rules) => {
+                let earliest = median_time_past(self, &parents, rules.window) + 1;
+                self.clock.max(earliest).min(self.clock.saturating_add(rules.max_future_drift))
+            },
+            None => self.clock,
+        };
+
+        BlockTemplate{
+            parents,
+            height: candidate.height,
+            size_of_past_set: candidate.size_of_past_set,
+            size_of_past_blue: candidate.size_of_past_blue,
+            target,
+            timestamp,
+            txs: Vec::new(),
+            miner: self.miner_key.as_ref().map(|key| key.public_key()),
+        }
+    }
+
+    /// Validate a mined block against the local dag, then add it. On success, it's counted as mined by this node.
+    ///
+    pub fn submit_block(&mut self, block: &BlockRaw, k: i32) -> Result<(), BlockError>{
+
+        if self.dag.contains_key(&block.name) {
+            return Err(BlockError::Duplicate(block.name.clone()));
+        }
+        if block.prev.is_empty() {
+            return Err(BlockError::NoParent(block.name.clone()));
+        }
+
+        let candidate = candidate_block(&block.name, &block.prev, self)?;
+        if candidate.height != block.height {
+            return Err(BlockError::Mismatch{block: block.name.clone(), field: "height", header: block.height, computed: candidate.height});
+        }
+        if candidate.size_of_past_set != block.size_of_past_set {
+            return Err(BlockError::Mismatch{block: block.name.clone(), field: "size_of_past_set", header: block.size_of_past_set, computed: candidate.size_of_past_set});
+        }
+
+        self.check_header(block)?;
+
+        node_insert_raw(block, self, k)?;
+        self.mined_blocks += 1;
+
+        Ok(())
+    }
+
+    /// The checks of a block header whose parents are all in the dag, on any received block before it's added.
+    /// With proof of work: the target is close to the locally computed one, and the hash doesn't exceed it.
+    /// With time rules: see 'check_timestamp'.
+    ///
+    pub fn check_header(&self, block: &BlockRaw) -> Result<(), BlockError>{
+
+        check_merkle_root(block)?;
+        check_signature(block)?;
+
+        if let Some(ref rules) = self.time_rules {
+            check_timestamp(block, self, rules)?;
+        }
+
+        if let Some(ref daa) = self.daa {
+            let computed = next_target(self, &block.prev, daa);
+            if !target_accepted(block.target, computed, daa) {
+                return Err(BlockError::BadTarget{block: block.name.clone(), target: block.target, computed});
+            }
+            if !check_pow(block) {
+                return Err(BlockError::InsufficientWork(block.name.clone()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A block with these parents, not linked into the dag: its height and past set are computed as 'dag_add_block' does.
+///
+pub(crate) fn candidate_block(name: &str, parents: &[String], node: &Node) -> Result<Block, BlockError>{
+
+    let mut prev: HashMap<String, Arc<RwLock<Block>>> = HashMap::new();
+    let mut height: u64 = 0;
+
+    for parent in parents {
+        let block = node.dag.get(parent).ok_or_else(|| BlockError::MissingParent{block: name.to_string(), parent: parent.clone()})?;
+        if prev.insert(parent.clone(), Arc::clone(block)).is_some() {
+            return Err(BlockError::DuplicateParent{block: name.to_string(), parent: parent.clone()});
+        }
+        height = height.max(block.read().unwrap().height + 1);
+    }
+
+    let mut candidate = Block{
+        name: name.to_string(),
+        height,
+        size_of_past_set: 0,
+        size_of_past_blue: 0,
+        is_blue: false,
+        size_of_anticone_blue: -1,
+        prev,
+        next: HashMap::new(),
+        tips_snapshot: HashMap::new(),
+        sequence: node.dag.len() as u64,
+        timestamp: 0,
+        target: 0,
+        nonce: 0,
+        merkle_root: EMPTY_ROOT,
+        miner: None,
+        signature: None,
+        txs: Vec::new(),
+    };
+    let (size_of_past_set, size_of_past_blue) = sizeof_pastset(&candidate);
+    candidate.size_of_past_set = size_of_past_set;
+    candidate.size_of_past_blue = size_of_past_blue;
+
+    Ok(candidate)
+}
@@ -0,0 +1,103 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::{HashSet,BinaryHeap};
+
+use blockdag::{Node,Block,BlockRaw,BlockError};
+
+/// Timestamp rules of the block headers.
+///
+/// A timestamp must exceed the median time past: the median timestamp of the last 'window' blue blocks of the block past,
+/// so that a miner can't move the time backwards. And it can't be more than 'max_future_drift' ahead of the node clock.
+///
+#[derive(Clone, Debug)]
+pub struct TimeRules{
+    pub window: usize,
+    pub max_future_drift: u64,                              // ms.
+}
+
+impl Default for TimeRules {
+
+    fn default() -> TimeRules {
+        TimeRules{
+            window: 11,
+            max_future_drift: 120_000,
+        }
+    }
+}
+
+/// Function providing the (timestamp, target) of the last 'window' blue blocks of the past of a new block with these parents,
+/// which satisfy the filter. The past is walked back from the parents, the block with the largest past firstly.
+///
+pub fn past_blue_window<F>(node: &Node, parents: &[String], window: usize, filter: F) -> Vec<(u64, u64)>
+    where F: Fn(&Block) -> bool {
+
+    let mut blues: Vec<(u64, u64)> = Vec::with_capacity(window);
+    let mut visited: HashSet<String> = HashSet::new();
+    let mut heap: BinaryHeap<(u64, String)> = BinaryHeap::new();
+
+    for name in parents {
+        if let Some(block) = node.dag.get(name) {
+            if visited.insert(name.clone()) {
+                heap.push((block.read().unwrap().size_of_past_set, name.clone()));
+            }
+        }
+    }
+
+    while let Some((_, name)) = heap.pop() {
+        if blues.len() >= window {
+            break;
+        }
+        let block = node.dag.get(&name).unwrap().read().unwrap();
+        if block.is_blue && filter(&block) {
+            blues.push((block.timestamp, block.target));
+        }
+        for (prev_name, prev) in &block.prev {
+            if visited.insert(prev_name.clone()) {
+                heap.push((prev.read().unwrap().size_of_past_set, prev_name.clone()));
+            }
+        }
+    }
+
+    blues
+}
+
+/// Function providing the median time past of a new block with these parents, see 'TimeRules'.
+///
+pub fn median_time_past(node: &Node, parents: &[String], window: usize) -> u64{
+
+    let mut timestamps: Vec<u64> = past_blue_window(node, parents, window.max(1), |_| true).into_iter().map(|(timestamp,_)| timestamp).collect();
+    if timestamps.is_empty() {
+        return 0;
+    }
+    timestamps.sort();
+    timestamps[timestamps.len() / 2]
+}
+
+/// Function providing the timestamp checks of a block header whose parents are all in the dag.
+///
+pub fn check_timestamp(block: &BlockRaw, node: &Node, rules: &TimeRules) -> Result<(), BlockError>{
+
+    let median = median_time_past(node, &block.prev, rules.window);
+    if block.timestamp <= median {
+        return Err(BlockError::TimestampTooOld{block: block.name.clone(), timestamp: block.timestamp, median});
+    }
+    if block.timestamp > node.clock.saturating_add(rules.max_future_drift) {
+        return Err(BlockError::TimestampInFuture{block: block.name.clone(), timestamp: block.timestamp, clock: node.clock});
+    }
+
+    Ok(())
+}
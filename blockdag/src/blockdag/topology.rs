@@ -0,0 +1,184 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use rand::Rng;
+
+use blockdag::Latency;
+
+const PAIRING_MAX_RETRY: usize = 100;
+
+/// Network topology of the simulation.
+///
+#[derive(Clone, Debug)]
+pub enum Topology{
+    FullMesh,                                               // every node is linked to every other node.
+    RandomRegular(usize),                                   // every node has the same number (degree) of random peers.
+    SmallWorld{degree: usize, rewire: f64},                 // Watts-Strogatz: a ring lattice where each link is rewired to a random node with probability 'rewire'.
+    Regions{regions: usize, inter_links: usize, inter_latency: Latency},  // full mesh inside a region, plus 'inter_links' links per node to other regions, with its own (high) latency.
+}
+
+/// One direction of a link between 2 nodes.
+///
+#[derive(Clone, Debug)]
+pub struct Link{
+    pub to: usize,
+    pub latency: Latency,
+}
+
+impl Topology {
+
+    /// Build the links of each node. Links are symmetric: if 'a' has a link to 'b', 'b' has a link to 'a' with the same latency.
+    ///
+    pub fn build<R: Rng>(&self, nodes: usize, latency: &Latency, rng: &mut R) -> Vec<Vec<Link>>{
+
+        let mut links: Vec<Vec<Link>> = vec![Vec::new(); nodes];
+
+        match *self {
+            Topology::FullMesh => {
+                for a in 0..nodes {
+                    for b in a+1..nodes {
+                        add_link(&mut links, a, b, latency);
+                    }
+                }
+            },
+            Topology::RandomRegular(degree) => {
+                for (a,b) in random_regular(nodes, degree, rng) {
+                    add_link(&mut links, a, b, latency);
+                }
+            },
+            Topology::SmallWorld{degree, rewire} => {
+                for (a,b) in small_world(nodes, degree, rewire, rng) {
+                    add_link(&mut links, a, b, latency);
+                }
+            },
+            Topology::Regions{regions, inter_links, ref inter_latency} => {
+                let regions = regions.max(1).min(nodes.max(1));
+                let region_of = |node: usize| node * regions / nodes;
+
+                for a in 0..nodes {
+                    for b in a+1..nodes {
+                        if region_of(a) == region_of(b) {
+                            add_link(&mut links, a, b, latency);
+                        }
+                    }
+                }
+
+                if regions > 1 {
+                    for a in 0..nodes {
+                        let mut linked = links[a].iter().filter(|l| region_of(l.to) != region_of(a)).count();
+                        let mut attempts = 0;
+                        while linked < inter_links && attempts < PAIRING_MAX_RETRY {
+                            attempts += 1;
+                            let b = rng.gen_range(0, nodes);
+                            if region_of(b) == region_of(a) || links[a].iter().any(|l| l.to == b) {
+                                continue;
+                            }
+                            add_link(&mut links, a, b, inter_latency);
+                            linked += 1;
+                        }
+                    }
+                }
+            },
+        }
+
+        links
+    }
+}
+
+fn add_link(links: &mut [Vec<Link>], a: usize, b: usize, latency: &Latency){
+    links[a].push(Link{to: b, latency: latency.clone()});
+    links[b].push(Link{to: a, latency: latency.clone()});
+}
+
+/// Random regular graph by the pairing model (Steger-Wormald variant): 'degree' stubs per node, pairs of random stubs
+/// are linked as long as it gives no self-loop and no duplicated link, restart if it gets stuck.
+/// If 'nodes*degree' is odd, one node gets one link less.
+///
+fn random_regular<R: Rng>(nodes: usize, degree: usize, rng: &mut R) -> Vec<(usize,usize)>{
+
+    let degree = degree.min(nodes.saturating_sub(1));
+    let mut edges: HashSet<(usize,usize)> = HashSet::new();
+
+    for _ in 0..PAIRING_MAX_RETRY {
+        let mut stubs: Vec<usize> = (0..nodes).flat_map(|n| ::std::iter::repeat_n(n, degree)).collect();
+        edges.clear();
+
+        let mut failures = 0;
+        while stubs.len() >= 2 && failures < PAIRING_MAX_RETRY * stubs.len() {
+            let i = rng.gen_range(0, stubs.len());
+            let j = rng.gen_range(0, stubs.len());
+            let (a,b) = (stubs[i].min(stubs[j]), stubs[i].max(stubs[j]));
+            if i == j || a == b || edges.contains(&(a,b)) {
+                failures += 1;
+                continue;
+            }
+            edges.insert((a,b));
+            stubs.swap_remove(i.max(j));
+            stubs.swap_remove(i.min(j));
+        }
+
+        if stubs.len() < 2 {
+            break;
+        }
+    }
+
+    if edges.len() < nodes * degree / 2 {
+        warn!("random_regular(): nodes={} degree={}. pairing failed, some nodes have less links.", nodes, degree);
+    }
+
+    let mut edges = edges.into_iter().collect::<Vec<(usize,usize)>>();
+    edges.sort();   // the hashset iteration order is random, sort it for a reproducible simulation.
+    edges
+}
+
+/// Watts-Strogatz small world graph: each node links to its 'degree/2' nearest neighbours on each side of a ring,
+/// then each link is rewired to a random node with probability 'rewire'.
+///
+fn small_world<R: Rng>(nodes: usize, degree: usize, rewire: f64, rng: &mut R) -> Vec<(usize,usize)>{
+
+    let half = (degree / 2).min(nodes.saturating_sub(1) / 2).max(1);
+    let mut edges: Vec<(usize,usize)> = Vec::new();
+
+    if nodes < 2 {
+        return edges;
+    }
+
+    for a in 0..nodes {
+        for j in 1..half+1 {
+            let b = (a + j) % nodes;
+            let edge = (a.min(b), a.max(b));
+            if edge.0 != edge.1 && !edges.contains(&edge) {
+                edges.push(edge);
+            }
+        }
+    }
+
+    for i in 0..edges.len() {
+        if rng.gen::<f64>() >= rewire {
+            continue;
+        }
+        let a = edges[i].0;
+        let b = rng.gen_range(0, nodes);
+        let edge = (a.min(b), a.max(b));
+        if a != b && !edges.contains(&edge) {
+            edges[i] = edge;
+        }
+    }
+
+    edges
+}
@@ -0,0 +1,108 @@
+// Copyright 2018 The rust-dag Authors
+// This file is part of the rust-dag library.
+//
+// The rust-dag library is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Lesser General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// The rust-dag library is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU Lesser General Public License for more details.
+//
+// You should have received a copy of the GNU Lesser General Public License
+// along with the rust-dag library. If not, see <http://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+use blockdag::{Node,get_stpq,dag_order};
+use blockdag::template::candidate_block;
+
+/// The virtual block: a block which would reference all the current tips, i.e. the view of the node on the whole dag.
+///
+/// 'calc_blue' colours the blocks relatively to the current tips, so the blue set of the virtual block is all the blue
+/// blocks of the dag. It's built again after each new block, see 'node_add_block'. Its past, computed as for a block
+/// template (see 'candidate_block'), is only computed when it's queried.
+///
+#[derive(Clone, Debug, Default)]
+pub struct VirtualBlock{
+    pub parents: Vec<String>,                               // the tips, in score order (see 'get_stpq').
+    pub selected_parent: Option<String>,                    // the tip with the best score. None for an empty dag.
+    pub blue_parents: Vec<String>,                          // the blue tips, in score order.
+    pub height: u64,
+    past: OnceLock<(u64,u64)>,                              // (blue blocks, blocks) of its past, on the first query.
+}
+
+impl PartialEq for VirtualBlock {
+
+    fn eq(&self, other: &VirtualBlock) -> bool {
+        self.parents == other.parents && self.blue_parents == other.blue_parents && self.height == other.height
+    }
+}
+
+impl VirtualBlock {
+
+    /// Function providing the virtual block over the current tips of a node.
+    ///
+    pub fn from_tips(node: &Node) -> VirtualBlock{
+
+        let parents: Vec<String> = get_stpq(&node.tips).into_iter().map(|(name,_,_)| name).collect();
+        if parents.is_empty() {
+            return VirtualBlock::default();
+        }
+
+        let blue_parents = parents.iter().filter(|name| node.tips[*name].read().unwrap().is_blue).cloned().collect();
+        let height = parents.iter().map(|name| node.tips[name].read().unwrap().height + 1).max().unwrap_or(0);
+
+        VirtualBlock{
+            selected_parent: parents.first().cloned(),
+            parents,
+            blue_parents,
+            height,
+            past: OnceLock::new(),
+        }
+    }
+
+    fn past(&self, node: &Node) -> (u64,u64){
+        *self.past.get_or_init(|| {
+            if self.parents.is_empty() {
+                return (0, 0);
+            }
+            let candidate = candidate_block("virtual", &self.parents, node).unwrap();
+            (candidate.size_of_past_blue, candidate.size_of_past_set)
+        })
+    }
+}
+
+impl Node {
+
+    /// The blue blocks of the dag, as seen by the virtual block.
+    ///
+    pub fn virtual_blue_score(&self) -> u64{
+        self.virtual_block.past(self).0
+    }
+
+    /// The tip the virtual block builds on, see 'VirtualBlock'.
+    ///
+    pub fn virtual_selected_parent(&self) -> Option<&str>{
+        self.virtual_block.selected_parent.as_deref()
+    }
+
+    pub fn virtual_past_size(&self) -> u64{
+        self.virtual_block.past(self).1
+    }
+
+    /// The blue set of the virtual block, i.e. all the blue blocks of the dag.
+    ///
+    pub fn virtual_blue_set(&self) -> HashSet<String>{
+        self.dag.values().map(|block| block.read().unwrap()).filter(|block| block.is_blue).map(|block| block.name.clone()).collect()
+    }
+
+    /// The order of the past of the virtual block, i.e. of the whole dag, see 'dag_order'.
+    ///
+    pub fn virtual_order(&self) -> Vec<String>{
+        dag_order(&self.dag)
+    }
+}
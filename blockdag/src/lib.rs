@@ -19,6 +19,13 @@ pub mod blockdag;
 
 #[macro_use]
 extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate rand;
+extern crate sha2;
+extern crate ed25519_dalek;
 
 #[cfg(test)]
 mod tests {
@@ -28,18 +35,41 @@ mod tests {
     extern crate rand;
     extern crate time;
 
-    use std::collections::HashMap;
+    use std::collections::{HashMap,HashSet};
     use std::sync::{Arc,RwLock};
     use std::sync::atomic::{AtomicBool,AtomicIsize};
     use std::sync::atomic::Ordering;
     use self::rand::Rng;
     use self::time::{PreciseTime};
     use std::thread;
+    use std::io;
+    use std::io::{Read,Write};
+    use std::net::TcpStream;
     use std::time::Duration;
     use std::sync::mpsc;
 
     use blockdag::{Node,BlockRaw};
-    use blockdag::{node_add_block,dag_print,dag_blue_print,dag_red_print,tips_anticone,sorted_keys_by_height,remove_past_future,update_tips,calc_blue,handle_block_rx,get_stpq};
+    use blockdag::{node_add_block,dag_print,dag_blue_print,dag_red_print,tips_anticone,sorted_keys_by_height,remove_past_future,update_tips,calc_blue,handle_block_rx,get_stpq,sizeof_pastset};
+    use blockdag::{dag_render_svg,dag_render_html,node_to_json,node_from_json,node_dump,node_from_dump,DumpError};
+    use blockdag::{Simulator,SimConfig,Latency,Topology,SimMetrics,sim_metrics};
+    use serde_json;
+    use blockdag::{dag_order,check_convergence};
+    use blockdag::{AttackerConfig,AttackRelease,sim_attack_report};
+    use blockdag::{PartitionConfig,PartitionCut,sim_partition_report};
+    use blockdag::{Message,BlockStash,StashConfig,DagSync,handle_message,handle_request,MAX_HEADERS};
+    use blockdag::{NetNode,NetError,MAX_FRAME};
+    use blockdag::{Relay,PeerInventory,Traffic,handle_inv,MAX_KNOWN_BLOCKS};
+    use blockdag::{ParentSelector,ParentStrategy,AllTips,TopScore,BlueTips,RandomSubset,MaxParents};
+    use blockdag::{BlockError};
+    use blockdag::{PowConfig,DaaConfig,header_hash,check_pow,solve,target_for};
+    use blockdag::{TimeRules,median_time_past};
+    use blockdag::{Transaction,TxOut,Ledger,TxError,LedgerUpdate,ledger_from_node,node_add_raw};
+    use blockdag::{OutPoint,Mempool,MempoolConfig};
+    use blockdag::{EMPTY_ROOT,merkle_root,tx_merkle_root,merkle_proof,verify_proof};
+    use blockdag::{MinerKey,check_signature};
+    use blockdag::{RewardConfig,Rewards,rewards_from_node};
+    use blockdag::{VirtualBlock,ChainChanged};
+    use blockdag::{ColouringEngine,ColouringError,CalcBlue,Ghostdag,node_add_block_with};
 
     #[test]
     fn test_fig3() {
@@ -172,6 +202,35 @@ mod tests {
         assert_eq!(result, "anticone of M = [(\"J\", 3), (\"L\", 3)]");
     }
 
+    #[test]
+    fn test_sizeof_pastset() {
+
+        let k: i32 = 3;
+
+        let node = Node::init("past size test");
+
+        let mut node_w = node.write().unwrap();
+
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+
+        // P is in the past of R by two paths, of lengths 1 and 2, and out of the past of the max parent M5.
+        node_add_block("P", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("Q", &vec!["P"], &mut node_w, k, true);
+        node_add_block("R", &vec!["P","Q"], &mut node_w, k, true);
+        let mut prev = String::from("Genesis");
+        for i in 1..6 {
+            let name = format!("M{}", i);
+            node_add_block(&name, &vec![prev.as_str()], &mut node_w, k, true);
+            prev = name;
+        }
+        node_add_block("X", &vec!["M5","R"], &mut node_w, k, true);
+
+        // Genesis, P, Q, R and M1..M5: P is counted once.
+        let x = node_w.dag.get("X").unwrap().read().unwrap();
+        assert_eq!(sizeof_pastset(&x).0, 9);
+        assert_eq!(x.size_of_past_set, 9);
+    }
+
 
     #[test]
     fn test_add_block() {
@@ -749,6 +808,13 @@ mod tests {
                             height: new_mined_block.height,
                             size_of_past_set: new_mined_block.size_of_past_set,
                             prev: prev_names,
+                            timestamp: 0,
+                            target: 0,
+                            nonce: 0,
+                            merkle_root: EMPTY_ROOT,
+                            miner: None,
+                            signature: None,
+                            txs: Vec::new(),
                         };
 
                         dispatcher_tx_clone.send(new_block_raw).unwrap();
@@ -902,4 +968,1623 @@ mod tests {
 
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn test_render() {
+
+        let k: i32 = 3;
+
+        let node = Node::init("fig3");
+
+        let mut node_w = node.write().unwrap();
+
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+
+        node_add_block("B", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("C", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("D", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("E", &vec!["Genesis"], &mut node_w, k, true);
+
+        node_add_block("F", &vec!["B","C"], &mut node_w, k, true);
+        node_add_block("H", &vec!["C","D","E"], &mut node_w, k, true);
+        node_add_block("I", &vec!["E"], &mut node_w, k, true);
+
+        let svg = dag_render_svg(&node_w);
+        assert!(svg.starts_with("<svg "));
+        assert_eq!(svg.matches("<g class=\"block\">").count(), 8);
+        assert_eq!(svg.matches("<line ").count(), 10);
+        assert!(svg.contains("<title>name=H&#10;height=2&#10;"));
+
+        let html = dag_render_html(&node_w);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains(&svg));
+
+        // blocks missing from the classmates get rows of their own, below the classmates.
+        node_w.classmates.insert(1, vec![String::from("E"), String::from("B")]);
+        let svg = dag_render_svg(&node_w);
+        let boxes: HashSet<&str> = svg.match_indices("<rect x=").map(|(start,_)| &svg[start..start + svg[start..].find(" width").unwrap()]).collect();
+        assert_eq!(boxes.len(), 8);
+    }
+
+    #[test]
+    fn test_json_dump() {
+
+        let k: i32 = 3;
+
+        let node = Node::init("json dump test");
+
+        let mut node_w = node.write().unwrap();
+
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+
+        // random dag, every round some blocks mined in parallel on the same tips.
+        let mut blocks_generated = 0;
+        while blocks_generated < 300 {
+            let score_stpq = get_stpq(&node_w.tips);
+            let classmate_blocks = rand::thread_rng().gen_range(1, k+2);
+
+            let mut round: Vec<(String, Vec<String>)> = Vec::new();
+            for _ in 0..classmate_blocks {
+                blocks_generated += 1;
+                let prev_blocks = rand::thread_rng().gen_range(1, score_stpq.len()+1);
+                let references = score_stpq.iter().take(prev_blocks).map(|(s,_,_)| s.clone()).collect::<Vec<String>>();
+                round.push((format!("{:04}", blocks_generated), references));
+            }
+            for (name, references) in &round {
+                let references_str = references.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+                node_add_block(name, &references_str, &mut node_w, k, true);
+            }
+        }
+
+        let json = node_to_json(&node_w, k);
+
+        let loaded = node_from_json(&json).unwrap();
+        let loaded = loaded.read().unwrap();
+        assert_eq!(loaded.size_of_dag, node_w.size_of_dag);
+        assert_eq!(loaded.height, node_w.height);
+        assert_eq!(dag_blue_print(&loaded.dag), dag_blue_print(&node_w.dag));
+        assert_eq!(node_to_json(&loaded, k), json);
+
+        // a tampered colouring must be detected on load.
+        let tampered = json.replacen("\"is_blue\": true", "\"is_blue\": false", 2);
+        let result = node_from_json(&tampered);
+        assert!(result.is_err());
+        println!("{}", result.err().unwrap());
+
+        // so must a tampered header, or a block twice.
+        let mut dump = node_dump(&node_w, k);
+        dump.blocks[5].merkle_root = [1u8; 32];
+        let name = dump.blocks[5].name.clone();
+        match node_from_dump(&dump) {
+            Err(DumpError::Rejected{block, ..}) => assert_eq!(block, name),
+            _ => panic!("tampered merkle root not rejected"),
+        }
+        let mut dump = node_dump(&node_w, k);
+        let duplicate = node_dump(&node_w, k).blocks.remove(3);
+        let name = duplicate.name.clone();
+        dump.blocks.push(duplicate);
+        match node_from_dump(&dump) {
+            Err(DumpError::Rejected{block, ..}) => assert_eq!(block, name),
+            _ => panic!("duplicate block not rejected"),
+        }
+
+        // and tampered node fields.
+        let mut dump = node_dump(&node_w, k);
+        dump.height += 1;
+        assert!(matches!(node_from_dump(&dump), Err(DumpError::Mismatch{field: "height", ..})));
+        let mut dump = node_dump(&node_w, k);
+        dump.size_of_dag -= 1;
+        assert!(matches!(node_from_dump(&dump), Err(DumpError::Mismatch{field: "size_of_dag", ..})));
+        let mut dump = node_dump(&node_w, k);
+        dump.classmates.get_mut(&1).unwrap().push(String::from("0000"));
+        assert!(matches!(node_from_dump(&dump), Err(DumpError::Mismatch{field: "classmates", ..})));
+    }
+
+    #[test]
+    fn test_simulator() {
+
+        let _ = env_logger::try_init();
+
+        let start = PreciseTime::now();
+
+        let mut sim = Simulator::new(SimConfig{nodes: 100, blocks: 1000, k: 3, ..SimConfig::default()});
+        sim.run();
+
+        println!("test_simulator(): 1000 blocks, 100 nodes. virtual time {}ms, real time {}ms", sim.clock, start.to(PreciseTime::now()).num_milliseconds());
+
+        assert_eq!(sim.mined.len(), 1000);
+        for (node, stash) in sim.nodes.iter().zip(sim.stashes.iter()) {
+            let node_r = node.read().unwrap();
+            assert_eq!(node_r.size_of_dag, 1001);
+            assert_eq!(stash.len(), 0);
+        }
+        let node0 = sim.nodes[0].read().unwrap();
+        println!("k={}, {}", 3, dag_blue_print(&node0.dag));
+
+        // same seed, same result.
+        let config = SimConfig{nodes: 20, blocks: 200, k: 3, latency: Latency::Exponential(300), seed: 7, ..SimConfig::default()};
+        let mut sim1 = Simulator::new(config.clone());
+        sim1.run();
+        let mut sim2 = Simulator::new(config);
+        sim2.run();
+
+        assert_eq!(sim1.clock, sim2.clock);
+        assert_eq!(sim1.mined, sim2.mined);
+        for (node1, node2) in sim1.nodes.iter().zip(sim2.nodes.iter()) {
+            assert_eq!(node_to_json(&node1.read().unwrap(), 3), node_to_json(&node2.read().unwrap(), 3));
+        }
+    }
+
+    #[test]
+    fn test_topology() {
+
+        let _ = env_logger::try_init();
+
+        let topologies = vec![
+            Topology::FullMesh,
+            Topology::RandomRegular(6),
+            Topology::SmallWorld{degree: 6, rewire: 0.2},
+            Topology::Regions{regions: 4, inter_links: 2, inter_latency: Latency::Uniform(500, 1500)},
+        ];
+
+        for k in [1, 3] {
+            for topology in &topologies {
+                let config = SimConfig{nodes: 40, blocks: 300, k, latency: Latency::Uniform(20, 200), topology: topology.clone(), seed: 1, ..SimConfig::default()};
+                let mut sim = Simulator::new(config);
+
+                if let Topology::RandomRegular(degree) = *topology {
+                    for links in &sim.links {
+                        assert_eq!(links.len(), degree);
+                    }
+                }
+
+                sim.run();
+
+                let mut reds = 0;
+                for node in &sim.nodes {
+                    let node_r = node.read().unwrap();
+                    assert_eq!(node_r.size_of_dag, 301);
+                    reds += node_r.dag.values().filter(|b| !b.read().unwrap().is_blue).count();
+                }
+                println!("k={}, topology={:?}: red rate={:.3}, clock={}ms", k, topology, reds as f64 / (40.0 * 301.0), sim.clock);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sim_metrics() {
+
+        let _ = env_logger::try_init();
+
+        let mut sim = Simulator::new(SimConfig{nodes: 30, blocks: 300, k: 3, topology: Topology::RandomRegular(4), seed: 3, ..SimConfig::default()});
+        sim.run();
+
+        let metrics = sim_metrics(&sim);
+        let a = &metrics.aggregated;
+        println!("{:?}", a);
+
+        assert_eq!(metrics.nodes.len(), 30);
+        assert_eq!(a.blocks_produced, 300);
+        assert_eq!(a.converged_blocks + a.diverged_blocks, 300);
+        for n in &metrics.nodes {
+            assert_eq!(n.blocks, 301);
+            assert!(n.red_fraction >= 0.0 && n.red_fraction < 1.0);
+            assert!(n.max_tips >= 1 && n.avg_tips >= 1.0);
+            assert!(n.avg_time_to_blue <= n.max_time_to_blue as f64);
+        }
+
+        // every recolouring has been seen when it happened, none is left for the final check.
+        for (node, stats) in sim.nodes.iter().zip(sim.stats.iter()) {
+            let mut last = stats.clone();
+            last.finish(&node.read().unwrap(), u64::MAX);
+            assert_eq!(last.colours, stats.colours);
+        }
+
+        let csv = metrics.to_csv();
+        assert_eq!(csv.lines().count(), 32);
+        assert_eq!(csv.lines().next().unwrap(), "node,blocks_produced,blocks,red_blocks,red_fraction,avg_tips,max_tips,avg_parents,\
+                   avg_anticone_blue,stash_peak,avg_time_to_blue,max_time_to_blue,converged_blocks,diverged_blocks,\
+                   avg_time_to_convergence,max_time_to_convergence");
+        assert!(csv.lines().all(|line| line.split(',').count() == 16));
+        let all: Vec<&str> = csv.lines().last().unwrap().split(',').collect();
+        assert_eq!(&all[..2], &["all", "300"]);
+        assert_eq!(all[7], format!("{:.3}", a.avg_parents));
+        assert_eq!(all[12..14], [a.converged_blocks.to_string(), a.diverged_blocks.to_string()]);
+
+        let json = metrics.to_json();
+        let parsed: SimMetrics = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.aggregated.converged_blocks, a.converged_blocks);
+    }
+
+    #[test]
+    fn test_convergence() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let fig4: Vec<(&str, Vec<&str>)> = vec![
+            ("Genesis", vec![]),
+            ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+            ("F", vec!["B","C"]), ("H", vec!["E"]), ("I", vec!["C","D"]),
+            ("J", vec!["F","D"]), ("K", vec!["J","I","E"]), ("L", vec!["F"]), ("N", vec!["D","H"]),
+            ("M", vec!["L","K"]), ("O", vec!["K"]), ("P", vec!["K"]), ("Q", vec!["N"]),
+            ("R", vec!["O","P","N"]),
+            ("S", vec!["Q"]), ("T", vec!["S"]), ("U", vec!["T"]),
+        ];
+
+        let nodes = [Node::init("node0"), Node::init("node1"), Node::init("node2")];
+        for (i, node) in nodes.iter().enumerate() {
+            let mut node_w = node.write().unwrap();
+            for &(name, ref references) in &fig4 {
+                // node2 has not received U yet.
+                if i == 2 && name == "U" {
+                    continue;
+                }
+                node_add_block(name, references, &mut node_w, k, true);
+            }
+        }
+
+        let node0 = nodes[0].read().unwrap();
+        let node1 = nodes[1].read().unwrap();
+        let node2 = nodes[2].read().unwrap();
+
+        assert_eq!(dag_order(&node0.dag), vec!["Genesis","B","C","D","F","I","J","E","K","O","P","L","M","H","N","R","Q","S","T","U"]);
+
+        let report = check_convergence(&[&node0, &node1]);
+        println!("{}", report);
+        assert!(report.converged);
+        assert_eq!(report.common_blocks, 20);
+
+        let report = check_convergence(&[&node0, &node1, &node2]);
+        println!("{}", report);
+        assert!(!report.converged);
+        assert_eq!(report.first_divergent, None);
+        assert_eq!(report.incomplete_nodes, vec!["node2"]);
+        assert_eq!(report.tips_mismatch, vec!["node2"]);
+        drop(node0);
+
+        // a recoloured block on node0 is detected. H turning blue also changes the order, from its past block E.
+        nodes[0].read().unwrap().dag.get("H").unwrap().write().unwrap().is_blue = true;
+        let report = check_convergence(&[&nodes[0].read().unwrap(), &node1]);
+        println!("{}", report);
+        assert_eq!(report.first_divergent, Some(String::from("E")));
+        assert_eq!(report.disagreeing_nodes, vec!["node1"]);
+        assert_eq!(report.colour_mismatches, 1);
+
+        // simulations: no parallel blocks, all nodes agree.
+        let mut sim = Simulator::new(SimConfig{nodes: 10, blocks: 50, k, block_interval: 10_000, latency: Latency::Constant(100), ..SimConfig::default()});
+        sim.run();
+        assert!(sim.check_convergence().converged);
+
+        // parallel blocks relayed over a random regular graph: all nodes get all the blocks and the same tips, but not in the same
+        // order, and the colouring of calc_blue depends on it.
+        let mut sim = Simulator::new(SimConfig{nodes: 30, blocks: 300, k, topology: Topology::RandomRegular(4), seed: 3, ..SimConfig::default()});
+        sim.run();
+        let report = sim.check_convergence();
+        assert!(!report.converged);
+        assert_eq!(report.common_blocks, 301);
+        assert!(report.incomplete_nodes.is_empty() && report.tips_mismatch.is_empty());
+        assert!(report.colour_mismatches > 0);
+    }
+
+
+    #[test]
+    fn test_attack() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+        let attacker = AttackerConfig{hash_fraction: 0.3, release: AttackRelease::AfterBlocks(20)};
+        let mut sim = Simulator::new(SimConfig{nodes: 20, blocks: 200, k, attacker: Some(attacker), seed: 5, ..SimConfig::default()});
+        sim.run();
+
+        let report = sim_attack_report(&sim).unwrap();
+        println!("{}", report);
+        assert_eq!(report.attacker_blocks, 20);
+        assert!(report.released_at.is_some());
+        assert_eq!(report.honest_nodes, 20);
+
+        // all the withheld blocks are received, and the attacker blocks are only in their own past.
+        for node in &sim.nodes {
+            let node_r = node.read().unwrap();
+            assert_eq!(node_r.dag.len(), sim.mined.len() + 1);
+        }
+        let attacker = sim.attacker.as_ref().unwrap();
+        let attacker_node = attacker.node.read().unwrap();
+        assert_eq!(attacker_node.dag.len(), 21);
+        assert_eq!(attacker_node.tips.len(), 1);
+
+        // a withheld chain doesn't get blue in a well connected network.
+        assert!(report.attacker_blue_fraction < 0.5);
+
+        // with proof of work, the attacker finds blocks with its share of the hash rate, and releases them. the first one has
+        // the same target on any node. the next ones follow the colours of the private chain on the attacker node, so the
+        // honest nodes may refuse their targets.
+        let attacker = AttackerConfig{hash_fraction: 0.3, release: AttackRelease::AfterBlocks(10)};
+        let mut sim = Simulator::new(SimConfig{nodes: 10, blocks: 100, k, attacker: Some(attacker), pow: Some(PowConfig::uniform(10, 1.0, 250)), seed: 5, ..SimConfig::default()});
+        sim.run();
+        let report = sim_attack_report(&sim).unwrap();
+        assert_eq!(report.attacker_blocks, 10);
+        assert!(report.released_at.is_some());
+        assert_eq!(sim.mined.iter().filter(|&&(_, miner, _)| miner == 10).count(), 10);
+        let first = &sim.attacker.as_ref().unwrap().blocks[0];
+        for node in &sim.nodes {
+            assert!(node.read().unwrap().dag.contains_key(first));
+        }
+
+        // no attacker, no report.
+        let mut sim = Simulator::new(SimConfig{nodes: 5, blocks: 10, k, ..SimConfig::default()});
+        sim.run();
+        assert!(sim_attack_report(&sim).is_none());
+
+        // release at a given time, even if the attacker has mined nothing yet.
+        let attacker = AttackerConfig{hash_fraction: 0.2, release: AttackRelease::AtTime(1)};
+        let mut sim = Simulator::new(SimConfig{nodes: 5, blocks: 20, k, attacker: Some(attacker), ..SimConfig::default()});
+        sim.run();
+        let report = sim_attack_report(&sim).unwrap();
+        assert_eq!(report.released_at, Some(1));
+    }
+
+
+    #[test]
+    fn test_partition() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        for cut in [PartitionCut::Delay, PartitionCut::Drop] {
+            let partition = PartitionConfig::halves(20, 2_000, 10_000, cut.clone());
+            let mut sim = Simulator::new(SimConfig{nodes: 20, blocks: 150, k, topology: Topology::RandomRegular(4), partition: Some(partition), seed: 7, ..SimConfig::default()});
+            sim.run();
+
+            let report = sim_partition_report(&sim).unwrap();
+            println!("{:?}: {}", cut, report);
+            assert_eq!(report.healed_at, Some(12_000));
+            assert!(report.blocks_before_heal > 0);
+            assert!(report.time_to_sync.is_some());
+
+            // no block is lost, all nodes get the whole dag at the end.
+            for node in &sim.nodes {
+                assert_eq!(node.read().unwrap().dag.len(), sim.mined.len() + 1);
+            }
+            for stash in &sim.stashes {
+                assert!(stash.is_empty());
+            }
+        }
+
+        // during the split, the two halves don't see each other's blocks.
+        let partition = PartitionConfig::halves(10, 0, 1_000_000, PartitionCut::Drop);
+        let mut sim = Simulator::new(SimConfig{nodes: 10, blocks: 50, k, partition: Some(partition), ..SimConfig::default()});
+        while sim.mined.len() < 50 {
+            sim.step();
+        }
+        for (name, miner, _) in &sim.mined {
+            for (number, node) in sim.nodes.iter().enumerate() {
+                if (number < 5) != (*miner < 5) {
+                    assert!(!node.read().unwrap().dag.contains_key(name));
+                }
+            }
+        }
+    }
+
+
+    #[test]
+    fn test_stash_sync() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let fig4: Vec<(&str, Vec<&str>)> = vec![
+            ("Genesis", vec![]),
+            ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+            ("F", vec!["B","C"]), ("H", vec!["E"]), ("I", vec!["C","D"]),
+            ("J", vec!["F","D"]), ("K", vec!["J","I","E"]), ("L", vec!["F"]), ("N", vec!["D","H"]),
+            ("M", vec!["L","K"]), ("O", vec!["K"]), ("P", vec!["K"]), ("Q", vec!["N"]),
+            ("R", vec!["O","P","N"]),
+            ("S", vec!["Q"]), ("T", vec!["S"]), ("U", vec!["T"]),
+        ];
+
+        let peer = Node::init("peer");
+        let mut peer_w = peer.write().unwrap();
+        for &(name, ref references) in &fig4 {
+            node_add_block(name, references, &mut peer_w, k, true);
+        }
+        let raws: HashMap<String,BlockRaw> = peer_w.dag.iter().map(|(name, block)| (name.clone(), block.read().unwrap().to_raw())).collect();
+        let raw = |name: &str| raws[name].clone();
+
+        // a fresh node receives U: it's stashed, and its missing parent requested, until U connects to Genesis.
+        let node = Node::init("node");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+        let mut stash = BlockStash::new(StashConfig::default());
+
+        let mut to_node = vec![Message::Block(raw("U"))];
+        let mut requests = 0;
+        let mut added: Vec<String> = Vec::new();
+        while let Some(message) = to_node.pop() {
+            let result = handle_message(message, &mut node_w, &mut stash, k, 0);
+            for reply in result.replies {
+                assert!(matches!(reply, Message::GetBlocks(_)));
+                requests += 1;
+                let mut answers = handle_message(reply, &mut peer_w, &mut BlockStash::new(StashConfig::default()), k, 0).replies;
+                answers.reverse();
+                to_node.append(&mut answers);
+            }
+            added.extend(result.added);
+        }
+        assert_eq!(requests, 6);                                // T, S, Q, N, then D and H together, then E.
+        assert_eq!(added, vec!["D","E","H","N","Q","S","T","U"]);
+        assert!(stash.is_empty());
+        for name in ["D","E","H","N","Q","S","T","U"] {
+            assert!(node_w.dag.contains_key(name));
+        }
+
+        // blocks received in reverse order, B at last: the blocks in B's future wait in the stash, and are all released by B, parents firstly.
+        let node2 = Node::init("node2");
+        let mut node2_w = node2.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node2_w, k, true);
+        let mut stash = BlockStash::new(StashConfig::default());
+        for &(name, _) in fig4[2..].iter().rev() {
+            handle_message(Message::Block(raw(name)), &mut node2_w, &mut stash, k, 0);
+        }
+        assert_eq!(stash.len(), 8);                             // F, J, K, L, M, O, P, R.
+        assert_eq!(stash.missing_parents(), vec!["B"]);
+
+        let result = handle_message(Message::Block(raw("B")), &mut node2_w, &mut stash, k, 0);
+        assert_eq!(result.added.len(), 9);
+        assert!(stash.is_empty());
+        assert_eq!(node2_w.dag.len(), fig4.len());
+        for (index, name) in result.added.iter().enumerate() {
+            for prev in &raw(name).prev {
+                assert!(!result.added[index..].contains(prev));
+            }
+        }
+
+        // eviction by size and by age, and requests again after the timeout.
+        let node3 = Node::init("node3");
+        let mut node3_w = node3.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node3_w, k, true);
+        let mut stash = BlockStash::new(StashConfig{max_size: 2, max_age: 1_000, request_timeout: 500});
+
+        let result = handle_message(Message::Block(raw("F")), &mut node3_w, &mut stash, k, 0);
+        assert_eq!(result.replies, vec![Message::GetBlocks(vec![String::from("B"), String::from("C")])]);
+        let result = handle_message(Message::Block(raw("I")), &mut node3_w, &mut stash, k, 100);
+        assert_eq!(result.replies, vec![Message::GetBlocks(vec![String::from("D")])]);
+        let result = handle_message(Message::Block(raw("L")), &mut node3_w, &mut stash, k, 200);
+        assert!(result.replies.is_empty());                     // F is stashed.
+        assert_eq!(stash.len(), 2);
+        assert_eq!(stash.evicted, 1);
+        assert!(!stash.contains("F"));
+
+        let result = handle_message(Message::Block(raw("J")), &mut node3_w, &mut stash, k, 600);
+        assert_eq!(result.replies, vec![Message::GetBlocks(vec![String::from("D"), String::from("F")])]);    // D requested again after the timeout.
+        assert_eq!(stash.evicted, 2);                           // I: 2 blocks at most.
+
+        let result = handle_message(Message::Block(raw("M")), &mut node3_w, &mut stash, k, 1_700);
+        assert_eq!(result.replies, vec![Message::GetBlocks(vec![String::from("L"), String::from("K")])]);    // L evicted before M.
+        assert_eq!(stash.len(), 1);                             // L and J too old.
+        assert!(stash.contains("M"));
+
+        // a block listing a missing parent twice is released once this parent is added.
+        let node4 = Node::init("node4");
+        let mut node4_w = node4.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node4_w, k, true);
+        let mut stash = BlockStash::new(StashConfig::default());
+        let twice = BlockRaw{name: String::from("X"), prev: vec![String::from("B"), String::from("B")], ..raw("F")};
+        let result = handle_message(Message::Block(twice), &mut node4_w, &mut stash, k, 0);
+        assert_eq!(result.replies, vec![Message::GetBlocks(vec![String::from("B")])]);
+        let result = handle_message(Message::Block(raw("B")), &mut node4_w, &mut stash, k, 100);
+        assert_eq!(result.added, vec!["B", "X"]);
+        assert_eq!(stash.len(), 0);
+
+        // a block rejected on receive doesn't stay in the stash of 'handle_block_rx'.
+        let mut block_stash: HashMap<String, BlockRaw> = HashMap::new();
+        let coinbase = Transaction{id: String::from("cb"), inputs: Vec::new(), outputs: vec![TxOut{owner: String::from("alice"), amount: 10}]};
+        let tampered = BlockRaw{txs: vec![coinbase], ..raw("C")};
+        assert!(handle_block_rx(tampered, &mut node4_w, &mut block_stash, k).is_empty());
+        assert!(block_stash.is_empty());
+
+        // a rejected parent: its stashed descendants are dropped, and it's not requested again.
+        let node5 = Node::init("node5");
+        let mut node5_w = node5.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node5_w, k, true);
+        let mut stash = BlockStash::new(StashConfig{max_size: 10, max_age: 1_000, request_timeout: 500});
+        handle_message(Message::Block(raw("F")), &mut node5_w, &mut stash, k, 0);
+        handle_message(Message::Block(raw("I")), &mut node5_w, &mut stash, k, 0);
+        let coinbase = Transaction{id: String::from("cb"), inputs: Vec::new(), outputs: vec![TxOut{owner: String::from("alice"), amount: 10}]};
+        let result = handle_message(Message::Block(BlockRaw{txs: vec![coinbase], ..raw("B")}), &mut node5_w, &mut stash, k, 100);
+        assert!(result.added.is_empty());
+        assert_eq!((stash.len(), stash.dropped), (1, 1));       // F dropped, I waits for C and D.
+        assert!(stash.is_rejected("B") && stash.is_rejected("F") && !stash.contains("F"));
+        assert_eq!(handle_inv(&[String::from("B"), String::from("C")], &node5_w, &stash, &mut HashMap::new(), 100),
+                   Some(Message::GetData(vec![String::from("C")])));
+        let result = handle_message(Message::Block(raw("B")), &mut node5_w, &mut stash, k, 700);
+        assert!(result.added.is_empty() && result.replies.is_empty());
+        let result = handle_message(Message::Block(raw("L")), &mut node5_w, &mut stash, k, 700);
+        assert!(result.replies.is_empty());                     // L references F: rejected, F not requested.
+        assert!(stash.is_rejected("L") && !stash.contains("L"));
+
+        // the stash expires without new blocks.
+        handle_message(Message::GetTips, &mut node5_w, &mut stash, k, 1_100);
+        assert!(stash.is_empty());
+        assert_eq!(stash.evicted, 1);
+    }
+
+
+    #[test]
+    fn test_dag_sync() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        // a peer with a 5000 blocks dag, made by a few nodes mining in parallel.
+        let mut sim = Simulator::new(SimConfig{nodes: 4, blocks: 5000, k, block_interval: 100, latency: Latency::Uniform(20, 200), ..SimConfig::default()});
+        sim.run();
+        let peer = sim.nodes[0].read().unwrap();
+        assert_eq!(peer.dag.len(), 5001);
+
+        let node = Node::init("fresh");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+
+        let mut stash = BlockStash::new(StashConfig::default());
+        let mut dag_sync = DagSync::new(500);
+        let mut requests = vec![dag_sync.start()];
+        let mut added: Vec<String> = Vec::new();
+        while let Some(request) = requests.pop() {
+            for reply in handle_request(&request, &peer) {
+                let mut result = dag_sync.handle_reply(reply, &mut node_w, &mut stash, k, 0);
+                requests.append(&mut result.replies);
+                added.append(&mut result.added);
+            }
+        }
+
+        assert!(dag_sync.is_done());
+        assert_eq!(dag_sync.requests, 1 + 10);                  // the tips, then 10 batches of headers.
+        assert_eq!(dag_sync.pending(), 0);
+        assert_eq!(added.len(), 5000);
+        assert_eq!(node_w.dag.len(), peer.dag.len());
+        assert_eq!(sorted_keys_by_height(&node_w.tips, false), sorted_keys_by_height(&peer.tips, false));
+        assert!(check_convergence(&[&peer, &node_w]).converged);
+
+        // a request for more headers gets 'MAX_HEADERS' at most.
+        let tips: Vec<String> = peer.tips.keys().cloned().collect();
+        match handle_request(&Message::GetHeaders{from: tips, max: usize::MAX}, &peer).pop() {
+            Some(Message::Headers(headers)) => assert_eq!(headers.len(), MAX_HEADERS),
+            other => panic!("unexpected answer {:?}", other),
+        }
+        assert_eq!(DagSync::new(usize::MAX).batch, MAX_HEADERS);
+
+        // already synced: only the tips are requested.
+        let mut dag_sync = DagSync::new(500);
+        let request = dag_sync.start();
+        let reply = handle_request(&request, &peer).pop().unwrap();
+        let result = dag_sync.handle_reply(reply, &mut node_w, &mut stash, k, 0);
+        assert!(dag_sync.is_done());
+        assert!(result.added.is_empty() && result.replies.is_empty());
+    }
+
+
+    #[test]
+    fn test_net_nodes() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let start_node = |name: &str| {
+            let node = Node::init(name);
+            node_add_block("Genesis", &Vec::new(), &mut node.write().unwrap(), k, true);
+            NetNode::start(node, k, "127.0.0.1:0").unwrap()
+        };
+
+        // node0 mines some blocks before the others join: they get them by the dag sync.
+        let net0 = start_node("node0");
+        for i in 0..50 {
+            net0.mine(&format!("node0-{}", i)).unwrap();
+        }
+
+        // a line: node0 <-> node1 <-> node2. blocks of node2 reach node0 by node1 relaying.
+        let net1 = start_node("node1");
+        let net2 = start_node("node2");
+        net1.connect(&net0.local_addr().to_string()).unwrap();
+        net2.connect(&net1.local_addr().to_string()).unwrap();
+
+        for i in 0..20 {
+            net0.mine(&format!("node0-{}", 50 + i)).unwrap();
+            net1.mine(&format!("node1-{}", i)).unwrap();
+            net2.mine(&format!("node2-{}", i)).unwrap();
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let nets = [&net0, &net1, &net2];
+        let mut synced = false;
+        for _ in 0..500 {
+            synced = nets.iter().all(|net| net.node().read().unwrap().dag.len() == 1 + 70 + 20 + 20);
+            if synced {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(synced);
+        assert_eq!(net1.peers(), 2);
+
+        let tips0 = sorted_keys_by_height(&net0.node().read().unwrap().tips, false);
+        for net in &nets[1..] {
+            assert_eq!(sorted_keys_by_height(&net.node().read().unwrap().tips, false), tips0);
+        }
+
+        // the handshake refuses a peer with another k.
+        let other = Node::init("other");
+        node_add_block("Genesis", &Vec::new(), &mut other.write().unwrap(), 2, true);
+        let other = NetNode::start(other, 2, "127.0.0.1:0").unwrap();
+        match other.connect(&net0.local_addr().to_string()) {
+            Err(NetError::Handshake(reason)) => println!("handshake failed as expected: {}", reason),
+            result => panic!("unexpected handshake result: {:?}", result),
+        }
+
+        // a peer sending a line longer than a frame is dropped, instead of growing the read buffer.
+        let mut stream = TcpStream::connect(net0.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        let chunk = vec![b'x'; 1024 * 1024];
+        for _ in 0..(MAX_FRAME as usize / chunk.len() + 1) {
+            if stream.write_all(&chunk).is_err() {
+                break;
+            }
+        }
+        let mut received = Vec::new();
+        match stream.read_to_end(&mut received) {
+            Ok(_) => {},
+            Err(ref e) if e.kind() == io::ErrorKind::ConnectionReset => {},
+            Err(e) => panic!("connection not closed: {}", e),
+        }
+        assert_eq!(net0.peers(), 1);
+    }
+
+
+    #[test]
+    fn test_inventory_relay() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let mut traffic: Vec<Traffic> = Vec::new();
+        for relay in [Relay::Push, Relay::Inventory] {
+            let mut sim = Simulator::new(SimConfig{nodes: 30, blocks: 200, k, topology: Topology::RandomRegular(6), relay: relay.clone(), seed: 11, ..SimConfig::default()});
+            sim.run();
+
+            for node in &sim.nodes {
+                assert_eq!(node.read().unwrap().dag.len(), 201);
+            }
+            let metrics = sim_metrics(&sim);
+            println!("{:?}: {:?}", relay, metrics.traffic);
+            traffic.push(metrics.traffic);
+        }
+
+        // each node gets each block only once: no duplicate, and one transfer per block and node but the miner.
+        let (push, inventory) = (&traffic[0], &traffic[1]);
+        assert!(push.duplicate_blocks > 0);
+        assert_eq!(inventory.duplicate_blocks, 0);
+        assert_eq!(inventory.block_transfers, 29 * 200);
+        assert!(inventory.bytes < push.bytes);
+        println!("bandwidth saved by inventory relay: {:.1}%", 100.0 * (1.0 - inventory.bytes as f64 / push.bytes as f64));
+
+        // announce only what a peer doesn't know.
+        let mut peer = PeerInventory::default();
+        peer.mark_known("A");
+        assert_eq!(peer.announce(&[String::from("A"), String::from("B")]), vec!["B"]);
+        assert!(peer.knows("B"));
+        assert!(peer.announce(&[String::from("B")]).is_empty());
+
+        // a peer inventory is bounded, the oldest blocks are forgotten firstly.
+        let mut peer = PeerInventory::new(3);
+        let names: Vec<String> = (0..5).map(|i| format!("{}", i)).collect();
+        assert_eq!(peer.announce(&names), names);
+        assert_eq!(peer.len(), 3);
+        assert!(!peer.knows("1") && peer.knows("2") && peer.knows("4"));
+        peer.mark_known("4");
+        assert_eq!(peer.announce(&names[..2]), names[..2].to_vec());
+        assert!(!peer.knows("2") && !peer.knows("3") && peer.knows("4"));
+        assert_eq!(PeerInventory::default().max_known, MAX_KNOWN_BLOCKS);
+    }
+
+    #[test]
+    fn test_parent_selection() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 1;
+
+        let node = Node::init("node1");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+        for name in ["B", "C", "D", "E"] {
+            node_add_block(name, &vec!["Genesis"], &mut node_w, k, true);
+        }
+        node_add_block("F", &vec!["B", "C"], &mut node_w, k, true);
+
+        let all = AllTips.select(&node_w);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0], "F");                            // the best score firstly.
+        assert_eq!(TopScore{n: 2}.select(&node_w), all[..2].to_vec());
+        assert_eq!(MaxParents{inner: Box::new(AllTips), max: 1}.select(&node_w), vec!["F"]);
+
+        let blue = BlueTips.select(&node_w);
+        assert!(!blue.is_empty());
+        for name in &blue {
+            assert!(node_w.tips.get(name).unwrap().read().unwrap().is_blue);
+        }
+
+        let mut random = RandomSubset::new(2, 7);
+        for _ in 0..10 {
+            let selected = random.select(&node_w);
+            assert_eq!(selected.len(), 2);
+            assert_eq!(selected[0], "F");
+            assert!(all.contains(&selected[1]));
+        }
+
+        // mining on all the tips merges them.
+        let raw = node_w.mine_block("G", &mut AllTips, k).unwrap();
+        assert_eq!(raw.prev.len(), 3);
+        assert_eq!(node_w.tips.len(), 1);
+        assert_eq!(node_w.mined_blocks, 1);
+        assert_eq!(node_w.mine_block("G", &mut AllTips, k), Err(BlockError::Duplicate(String::from("G"))));
+        drop(node_w);
+
+        // a single reference per block makes a wider dag, with more red blocks.
+        let mut results: Vec<(f64, f64, f64)> = Vec::new();
+        for parents in [ParentStrategy::KPlusOne, ParentStrategy::MaxParents(Box::new(ParentStrategy::AllTips), 1)] {
+            let mut sim = Simulator::new(SimConfig{nodes: 20, blocks: 200, k: 3, block_interval: 100, parents: parents.clone(), seed: 5, ..SimConfig::default()});
+            sim.run();
+            let a = sim_metrics(&sim).aggregated;
+            println!("{:?}: avg_parents={:.3}, avg_tips={:.3}, red_fraction={:.4}", parents, a.avg_parents, a.avg_tips, a.red_fraction);
+            results.push((a.avg_parents, a.avg_tips, a.red_fraction));
+        }
+        assert!(results[0].0 > 1.0);
+        assert!((results[1].0 - 1.0).abs() < 1e-9);
+        assert!(results[1].1 > results[0].1);
+        assert!(results[1].2 > results[0].2);
+    }
+
+    #[test]
+    fn test_block_template() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let node = Node::init("node1");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+        node_add_block("B", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("C", &vec!["Genesis"], &mut node_w, k, true);
+        node_add_block("D", &vec!["B"], &mut node_w, k, true);
+
+        let template = node_w.build_block_template(&mut AllTips);
+        assert_eq!(template.parents, vec!["D", "C"]);
+        assert_eq!(template.height, 3);
+        assert_eq!(template.size_of_past_set, 4);
+
+        // the template doesn't change the dag.
+        assert_eq!(node_w.dag.len(), 4);
+        assert_eq!(node_w.tips.len(), 2);
+
+        // rejected headers.
+        let header = template.header("E");
+        assert_eq!(node_w.submit_block(&template.header("D"), k), Err(BlockError::Duplicate(String::from("D"))));
+        assert_eq!(node_w.submit_block(&BlockRaw{prev: Vec::new(), ..header.clone()}, k), Err(BlockError::NoParent(String::from("E"))));
+        assert_eq!(node_w.submit_block(&BlockRaw{prev: vec![String::from("D"), String::from("X")], ..header.clone()}, k),
+                   Err(BlockError::MissingParent{block: String::from("E"), parent: String::from("X")}));
+        assert_eq!(node_w.submit_block(&BlockRaw{prev: vec![String::from("D"), String::from("D")], ..header.clone()}, k),
+                   Err(BlockError::DuplicateParent{block: String::from("E"), parent: String::from("D")}));
+        let error = node_w.submit_block(&BlockRaw{size_of_past_set: 5, ..header.clone()}, k).unwrap_err();
+        assert_eq!(error, BlockError::Mismatch{block: String::from("E"), field: "size_of_past_set", header: 5, computed: 4});
+        assert_eq!(format!("{}", error), "block E: header size_of_past_set=5 but computed size_of_past_set=4");
+        assert_eq!(node_w.submit_block(&BlockRaw{height: 2, ..header.clone()}, k).unwrap_err(),
+                   BlockError::Mismatch{block: String::from("E"), field: "height", header: 2, computed: 3});
+        assert_eq!(node_w.dag.len(), 4);
+
+        // the mined block.
+        assert_eq!(node_w.submit_block(&header, k), Ok(()));
+        assert_eq!(node_w.mined_blocks, 1);
+        assert_eq!(node_w.tips.len(), 1);
+        let block = node_w.dag.get("E").unwrap().read().unwrap();
+        assert_eq!((block.height, block.size_of_past_set), (template.height, template.size_of_past_set));
+        assert_eq!(block.size_of_past_blue, template.size_of_past_blue);
+        drop(block);
+        drop(node_w);
+
+        // the simulated miners submit their blocks through a template, which checks size_of_past_set: the exact size of the past set.
+        let mut sim = Simulator::new(SimConfig{nodes: 10, blocks: 300, k, block_interval: 50, seed: 3, ..SimConfig::default()});
+        sim.run();
+        let node = sim.nodes[0].read().unwrap();
+        for block in node.dag.values() {
+            let block = block.read().unwrap();
+            let mut past: HashSet<String> = HashSet::new();
+            let mut stack: Vec<String> = block.prev.keys().cloned().collect();
+            while let Some(name) = stack.pop() {
+                if past.insert(name.clone()) {
+                    stack.extend(node.dag.get(&name).unwrap().read().unwrap().prev.keys().cloned());
+                }
+            }
+            assert_eq!(block.size_of_past_set, past.len() as u64, "block {}", block.name);
+        }
+    }
+
+    #[test]
+    fn test_pow_daa() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        // a node with proof of work only accepts blocks with the expected target and a hash below it.
+        let node = Node::init("node1");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+        node_w.daa = Some(DaaConfig{initial_target: target_for(50.0), ..DaaConfig::default()});
+
+        let mut header = node_w.build_block_template(&mut AllTips).header("B");
+        assert_eq!(header.target, target_for(50.0));
+        header.timestamp = 1000;
+        assert!(solve(&mut header, 100_000));
+        assert!(check_pow(&header));
+        assert!(header_hash(&header) <= header.target);
+
+        let mut easy = header.clone();
+        easy.target = header.target.saturating_mul(4);
+        assert!(solve(&mut easy, 100_000));
+        assert_eq!(node_w.submit_block(&easy, k), Err(BlockError::BadTarget{block: String::from("B"), target: easy.target, computed: header.target}));
+        while check_pow(&header) {
+            header.nonce += 1;
+        }
+        assert_eq!(node_w.submit_block(&header, k), Err(BlockError::InsufficientWork(String::from("B"))));
+
+        // a relayed block goes through the same checks.
+        let mut block_stash: HashMap<String, BlockRaw> = HashMap::new();
+        assert!(handle_block_rx(header.clone(), &mut node_w, &mut block_stash, k).is_empty());
+        assert!(!node_w.dag.contains_key("B") && block_stash.is_empty());
+        assert!(solve(&mut header, 100_000));
+        assert_eq!(node_w.submit_block(&header, k), Ok(()));
+
+        let block = node_w.dag.get("B").unwrap().read().unwrap();
+        assert_eq!((block.timestamp, block.target, block.nonce), (header.timestamp, header.target, header.nonce));
+        assert_eq!(block.to_raw(), header);
+        drop(block);
+        drop(node_w);
+
+        // starting 4 times too easy, the difficulty adjustment brings the block interval back to the target.
+        let mut pow = PowConfig::uniform(20, 1.0, 250);
+        pow.daa.initial_target = pow.daa.initial_target.saturating_mul(4);
+        let mut sim = Simulator::new(SimConfig{nodes: 20, blocks: 600, k, pow: Some(pow), seed: 1, ..SimConfig::default()});
+        sim.run();
+
+        for node in &sim.nodes {
+            assert_eq!(node.read().unwrap().dag.len(), 601);
+        }
+        let times: Vec<u64> = sim.mined.iter().map(|&(_,_,time)| time).collect();
+        let interval = (times[599] - times[300]) as f64 / 299.0;
+        println!("block interval over the last 300 blocks: {:.1}ms", interval);
+        assert!(interval > 200.0 && interval < 300.0);
+
+        // blocks are found in proportion of the hash rates.
+        let mut pow = PowConfig::uniform(10, 1.0, 250);
+        pow.hash_rates[0] = 9.0;
+        let mut sim = Simulator::new(SimConfig{nodes: 10, blocks: 400, k, pow: Some(pow), seed: 2, ..SimConfig::default()});
+        sim.run();
+        let share = sim.mined.iter().filter(|&&(_,miner,_)| miner == 0).count() as f64 / 400.0;
+        println!("share of node0 with half of the hash rate: {:.3}", share);
+        assert!(share > 0.4 && share < 0.6);
+    }
+
+    #[test]
+    fn test_timestamps() {
+
+        let _ = env_logger::try_init();
+
+        let k: i32 = 3;
+
+        let node = Node::init("node1");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, k, true);
+        node_w.time_rules = Some(TimeRules{window: 5, max_future_drift: 1_000});
+
+        // a chain of blocks mined every 100ms.
+        for i in 1..=8u64 {
+            node_w.clock = i * 100;
+            let template = node_w.build_block_template(&mut AllTips);
+            assert_eq!(template.timestamp, i * 100);
+            node_w.submit_block(&template.header(&format!("B{}", i)), k).unwrap();
+        }
+        assert_eq!(node_w.dag.get("B8").unwrap().read().unwrap().timestamp, 800);
+
+        // the last 5 blue blocks are B8..B4: median 600.
+        let tips = vec![String::from("B8")];
+        assert_eq!(median_time_past(&node_w, &tips, 5), 600);
+
+        let header = node_w.build_block_template(&mut AllTips).header("C");
+        let at = |timestamp: u64| BlockRaw{timestamp, ..header.clone()};
+        assert_eq!(node_w.submit_block(&at(600), k), Err(BlockError::TimestampTooOld{block: String::from("C"), timestamp: 600, median: 600}));
+        assert_eq!(node_w.submit_block(&at(1_801), k), Err(BlockError::TimestampInFuture{block: String::from("C"), timestamp: 1_801, clock: 800}));
+        assert_eq!(format!("{}", node_w.submit_block(&at(1_801), k).unwrap_err()), "block C: timestamp 1801 too far ahead of the clock 800");
+
+        // a block mined in the past of the median, by a node with a late clock, still gets a valid timestamp.
+        node_w.clock = 300;
+        let template = node_w.build_block_template(&mut AllTips);
+        assert_eq!(template.timestamp, 601);
+        assert_eq!(node_w.submit_block(&template.header("C"), k), Ok(()));
+
+        // the template timestamp doesn't go beyond the future drift, even when the median time past does.
+        node_w.time_rules = Some(TimeRules{window: 5, max_future_drift: 100});
+        assert_eq!(node_w.build_block_template(&mut AllTips).timestamp, 400);
+        match node_w.mine_block("D", &mut AllTips, k) {
+            Err(BlockError::TimestampTooOld{timestamp: 400, ..}) => {},
+            result => panic!("unexpected mining result: {:?}", result),
+        }
+        drop(node_w);
+
+        // in simulation, all the blocks pass the timestamp checks of all the nodes.
+        let mut sim = Simulator::new(SimConfig{nodes: 20, blocks: 300, k, time_rules: Some(TimeRules::default()), seed: 4, ..SimConfig::default()});
+        sim.run();
+        for node in &sim.nodes {
+            assert_eq!(node.read().unwrap().dag.len(), 301);
+        }
+        for (name, _, time) in &sim.mined {
+            assert_eq!(sim.nodes[0].read().unwrap().dag.get(name).unwrap().read().unwrap().timestamp, *time);
+        }
+    }
+
+    #[test]
+    fn test_ledger() {
+
+        let _ = env_logger::try_init();
+
+        let output = |owner: &str, amount: u64| TxOut{owner: String::from(owner), amount};
+        let tx = |id: &str, inputs: &[&Transaction], outputs: Vec<TxOut>| Transaction{
+            id: String::from(id),
+            inputs: inputs.iter().map(|input| input.outpoint(0)).collect(),
+            outputs,
+        };
+
+        let fund = tx("fund", &[], vec![output("alice", 10)]);
+        let pay_bob = tx("pay-bob", &[&fund], vec![output("bob", 10)]);
+        let pay_carol = tx("pay-carol", &[&fund], vec![output("carol", 9)]);  // double spend of 'fund'.
+        let bob_dave = tx("bob-dave", &[&pay_bob], vec![output("dave", 10)]);
+        let carol_erin = tx("carol-erin", &[&pay_carol], vec![output("erin", 9)]);
+
+        let block = |name: &str, prev: &[&str], height: u64, size_of_past_set: u64, txs: Vec<Transaction>| BlockRaw{
+            name: String::from(name),
+            height,
+            size_of_past_set,
+            prev: prev.iter().map(|p| String::from(*p)).collect(),
+            timestamp: 0,
+            target: 0,
+            nonce: 0,
+            merkle_root: tx_merkle_root(&txs),
+            miner: None,
+            signature: None,
+            txs,
+        };
+        let f = block("F", &["Genesis"], 1, 1, vec![fund.clone()]);
+        let x = block("X", &["F"], 2, 2, vec![pay_bob.clone()]);
+        let y = block("Y", &["F"], 2, 2, vec![pay_carol.clone()]);
+        let z = block("Z", &["X", "Y"], 3, 4, vec![bob_dave.clone(), carol_erin.clone()]);
+
+        // the conflicting blocks reach the nodes in different orders, the last one through the stash.
+        let arrivals = [vec![&f, &x, &y, &z], vec![&f, &y, &x, &z], vec![&z, &y, &x, &f]];
+        let mut ledgers: Vec<Ledger> = Vec::new();
+        for (i, arrival) in arrivals.iter().enumerate() {
+            let node = Node::init(&format!("node{}", i));
+            let mut node_w = node.write().unwrap();
+            node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+            let mut stash = BlockStash::new(StashConfig::default());
+            for raw in arrival {
+                handle_message(Message::Block((*raw).clone()), &mut node_w, &mut stash, 3, 0);
+            }
+            assert_eq!(node_w.dag.len(), 5);
+            assert_eq!(node_w.dag.get("Z").unwrap().read().unwrap().txs, z.txs);
+            ledgers.push(ledger_from_node(&node_w));
+        }
+
+        // X is ordered before Y: its spend wins on all nodes.
+        for ledger in &ledgers {
+            assert_eq!(ledger.blocks, vec!["Genesis", "F", "X", "Y", "Z"]);
+            assert_eq!(ledger.accepted.get("pay-bob").map(|b| b.as_str()), Some("X"));
+            assert_eq!(ledger.accepted.get("bob-dave").map(|b| b.as_str()), Some("Z"));
+            assert_eq!(ledger.rejected, vec![
+                (String::from("Y"), TxError::DoubleSpend{tx: String::from("pay-carol"), input: fund.outpoint(0), spent_by: String::from("pay-bob")}),
+                (String::from("Z"), TxError::MissingInput{tx: String::from("carol-erin"), input: pay_carol.outpoint(0)}),
+            ]);
+            assert_eq!((ledger.balance("alice"), ledger.balance("bob"), ledger.balance("carol")), (0, 0, 0));
+            assert_eq!((ledger.balance("dave"), ledger.balance("erin")), (10, 0));
+            assert_eq!(ledger.utxos, ledgers[0].utxos);
+        }
+
+        // a red block is applied too: its conflicting transaction is rejected, the others accepted.
+        let node = Node::init("node3");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 0, true);
+        let red_coinbase = tx("red-coinbase", &[], vec![output("carol", 5)]);
+        for raw in [&f, &x, &block("Y", &["F"], 2, 2, vec![pay_carol.clone(), red_coinbase])] {
+            node_add_raw(raw, &mut node_w, 0);
+        }
+        assert!(!node_w.dag.get("Y").unwrap().read().unwrap().is_blue);
+        let ledger = ledger_from_node(&node_w);
+        assert!(matches!(ledger.check_tx(&pay_carol), Err(TxError::DoubleSpend{..})));
+        assert_eq!(ledger.accepted.get("red-coinbase").map(|b| b.as_str()), Some("Y"));
+        assert_eq!(ledger.balance("carol"), 5);
+        assert_eq!(format!("{}", ledger.rejected[0].1), "transaction pay-carol spends fund:0 already spent by pay-bob");
+
+        // the other checks.
+        assert_eq!(ledger.check_tx(&fund), Err(TxError::Duplicate(String::from("fund"))));
+        let overspend = tx("overspend", &[&pay_bob], vec![output("bob", 11)]);
+        assert_eq!(ledger.check_tx(&overspend), Err(TxError::Overspend{tx: String::from("overspend"), inputs: 10, outputs: 11}));
+        let twice = Transaction{inputs: vec![pay_bob.outpoint(0), pay_bob.outpoint(0)], ..overspend.clone()};
+        assert_eq!(ledger.check_tx(&twice), Err(TxError::DuplicateInput{tx: String::from("overspend"), input: pay_bob.outpoint(0)}));
+
+        // amounts overflowing u64, in the outputs or in the inputs.
+        let split = tx("split", &[&pay_bob], vec![output("bob", u64::MAX), output("bob", 1)]);
+        assert_eq!(ledger.check_tx(&split), Err(TxError::Overflow(String::from("split"))));
+        let big = tx("big", &[], vec![output("dave", u64::MAX)]);
+        let small = tx("small", &[], vec![output("dave", 1)]);
+        let mut rich = Ledger::new();
+        assert!(rich.apply_block("R", &[big.clone(), small.clone()]).is_empty());
+        assert_eq!(rich.balance("dave"), u64::MAX);
+        let merge = tx("merge", &[&big, &small], vec![output("dave", 1)]);
+        assert_eq!(rich.check_tx(&merge), Err(TxError::Overflow(String::from("merge"))));
+        assert_eq!(Mempool::new(MempoolConfig::default()).check_tx(&merge, &rich), Err(TxError::Overflow(String::from("merge"))));
+    }
+
+    #[test]
+    fn test_ledger_reorg() {
+
+        let _ = env_logger::try_init();
+
+        let config = SimConfig{nodes: 10, blocks: 150, k: 3, latency: Latency::Exponential(300), seed: 11, ..SimConfig::default()};
+        let mut sim = Simulator::new(config);
+        sim.run();
+
+        // replay the blocks of a node in its arrival order, each with a coinbase and a spend of an older coinbase,
+        // two blocks spending the same one: the double spends are won by the ordering, which keeps changing.
+        let mut raws: Vec<(u64, BlockRaw)> = sim.nodes[1].read().unwrap().dag.values()
+            .map(|block| block.read().unwrap()).filter(|block| block.name != "Genesis")
+            .map(|block| (block.sequence, block.to_raw())).collect();
+        raws.sort_by_key(|&(sequence,_)| sequence);
+
+        let node = Node::init("replay");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+
+        let mut ledger = Ledger::new();
+        let mut lagging = Ledger::new();
+        node_w.max_colour_log = 40;
+        let mut rolled_back = 0;
+        for (i, (_, raw)) in raws.iter_mut().enumerate() {
+            raw.txs.push(Transaction{id: format!("cb{}", i), inputs: Vec::new(), outputs: vec![TxOut{owner: format!("m{}", i % 5), amount: 10}]});
+            raw.txs.push(Transaction{id: format!("sp{}", i), inputs: vec![OutPoint{tx: format!("cb{}", i / 2), index: 0}], outputs: vec![TxOut{owner: format!("m{}", i % 3), amount: 10}]});
+            raw.merkle_root = tx_merkle_root(&raw.txs);
+            assert!(node_add_raw(raw, &mut node_w, 3));
+
+            let update = ledger.update(&node_w);
+            rolled_back += update.rolled_back;
+
+            let expected = ledger_from_node(&node_w);
+            assert_eq!(ledger.blocks, expected.blocks);
+            assert_eq!(ledger.utxos, expected.utxos);
+            assert_eq!(ledger.spent, expected.spent);
+            assert_eq!(ledger.accepted, expected.accepted);
+            assert_eq!(ledger.rejected, expected.rejected);
+
+            // an update behind the kept colour log computes the whole order again.
+            if i % 30 == 29 {
+                assert!(node_w.colour_log_since(0).is_none());
+                lagging.update(&node_w);
+                assert_eq!(lagging.blocks, expected.blocks);
+                assert_eq!(lagging.utxos, expected.utxos);
+            }
+        }
+        println!("test_ledger_reorg(): {} blocks, {} rolled back, {} rejected", raws.len(), rolled_back, ledger.rejected.len());
+        assert!(rolled_back > 0);
+        assert!(!ledger.rejected.is_empty());
+
+        // without a change, nothing is applied again. after a partial roll back, the rest is applied again.
+        assert_eq!(ledger.update(&node_w), LedgerUpdate::default());
+        for _ in 0..20 {
+            ledger.rollback();
+        }
+        assert_eq!(ledger.update(&node_w), LedgerUpdate{rolled_back: 0, applied: 20});
+        assert_eq!(ledger.blocks, dag_order(&node_w.dag));
+
+        // rolling everything back empties the ledger.
+        while ledger.rollback().is_some() {}
+        assert!(ledger.utxos.is_empty() && ledger.spent.is_empty() && ledger.accepted.is_empty() && ledger.rejected.is_empty());
+    }
+
+    #[test]
+    fn test_mempool() {
+
+        let _ = env_logger::try_init();
+
+        let node = Node::init("miner");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+
+        // a block funding alice with 40 outputs.
+        let fund = Transaction{id: String::from("fund"), inputs: Vec::new(), outputs: (0..40).map(|_| TxOut{owner: String::from("alice"), amount: 10}).collect()};
+        let mut template = node_w.build_block_template(&mut TopScore{n: 4});
+        template.txs.push(fund.clone());
+        node_w.submit_block(&template.header("F"), 3).unwrap();
+
+        let mut ledger = ledger_from_node(&node_w);
+        let mut mempool = Mempool::new(MempoolConfig{max_txs: 41, max_block_txs: 10, shards: 2});
+        let pay = |id: String, input: OutPoint, amount: u64| Transaction{id, inputs: vec![input], outputs: vec![TxOut{owner: String::from("bob"), amount}]};
+        for i in 0..40 {
+            mempool.add(pay(format!("pay{}", i), fund.outpoint(i), 10), &ledger).unwrap();
+        }
+
+        // conflicts with the virtual state, i.e. the ledger and the mempool.
+        assert_eq!(mempool.add(fund.clone(), &ledger), Err(TxError::Duplicate(String::from("fund"))));
+        assert_eq!(mempool.add(pay(String::from("again"), fund.outpoint(0), 10), &ledger),
+                   Err(TxError::DoubleSpend{tx: String::from("again"), input: fund.outpoint(0), spent_by: String::from("pay0")}));
+        assert_eq!(mempool.add(pay(String::from("more"), OutPoint{tx: String::from("pay1"), index: 0}, 11), &ledger),
+                   Err(TxError::Overspend{tx: String::from("more"), inputs: 10, outputs: 11}));
+        mempool.add(pay(String::from("child"), OutPoint{tx: String::from("pay1"), index: 0}, 10), &ledger).unwrap();
+        assert_eq!(mempool.add(pay(String::from("full"), OutPoint{tx: String::from("pay2"), index: 0}, 10), &ledger),
+                   Err(TxError::MempoolFull(String::from("full"))));
+        assert_eq!(mempool.len(), 41);
+
+        // two miners of other shards, with the same dag, mine in parallel at the same height.
+        let other_name = (0..).map(|i| format!("miner{}", i)).find(|name| mempool.miner_shard(name) != mempool.miner_shard("miner")).unwrap();
+        let other = Node::init(&other_name);
+        let mut other_w = other.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut other_w, 3, true);
+        assert!(node_add_raw(&node_w.dag["F"].read().unwrap().to_raw(), &mut other_w, 3));
+        let mut first = node_w.build_block_template(&mut TopScore{n: 4});
+        let mut second = other_w.build_block_template(&mut TopScore{n: 4});
+        assert_eq!(first.height, second.height);
+        mempool.fill_template(&mut first, &node_w);
+        mempool.fill_template(&mut second, &other_w);
+        assert_eq!((first.txs.len(), second.txs.len()), (10, 10));
+        assert!(first.txs.iter().all(|tx| !second.txs.contains(tx)));
+        assert!(first.txs.iter().all(|tx| mempool.shard(tx) == mempool.miner_shard("miner")));
+        assert!(second.txs.iter().all(|tx| mempool.shard(tx) == mempool.miner_shard(&other_name)));
+        node_w.submit_block(&first.header("X"), 3).unwrap();
+        other_w.submit_block(&second.header("Y"), 3).unwrap();
+        assert!(node_add_raw(&other_w.dag["Y"].read().unwrap().to_raw(), &mut node_w, 3));
+
+        // the accepted transactions leave the mempool, a child comes after its parent.
+        ledger.update(&node_w);
+        let removed = mempool.update(&ledger);
+        assert_eq!(removed.len(), 20);
+        assert_eq!(mempool.len(), 21);
+        let mut third = node_w.build_block_template(&mut TopScore{n: 4});
+        mempool.fill_template(&mut third, &node_w);
+        for (i, tx) in third.txs.iter().enumerate() {
+            for input in &tx.inputs {
+                assert!(ledger.utxos.contains_key(input) || third.txs[..i].iter().any(|parent| parent.id == input.tx));
+            }
+        }
+        node_w.submit_block(&third.header("Z"), 3).unwrap();
+        ledger.update(&node_w);
+        assert!(ledger.rejected.is_empty());
+        assert_eq!(mempool.update(&ledger).len(), 10);
+
+        // a child of the miner shard, spending a parent of the other shard, is selected right after its parent.
+        let mut mempool = Mempool::new(MempoolConfig{max_txs: 2, max_block_txs: 2, shards: 2});
+        let unspent = ledger.utxos.keys().find(|outpoint| outpoint.tx == "fund").unwrap().clone();
+        let parent = pay(String::from("parent"), unspent, 10);
+        let child = (0..).map(|i| pay(format!("child{}", i), parent.outpoint(0), 10)).find(|child| mempool.shard(child) != mempool.shard(&parent)).unwrap();
+        mempool.add(parent.clone(), &ledger).unwrap();
+        mempool.add(child.clone(), &ledger).unwrap();
+        let miner = if mempool.miner_shard("miner") == mempool.shard(&child) { &node_w } else { &other_w };
+        assert_eq!(mempool.select(miner), vec![parent, child]);
+    }
+
+    #[test]
+    fn test_merkle_root() {
+
+        let _ = env_logger::try_init();
+
+        // proofs of all the leaves, for odd and even trees.
+        let leaf = |i: u8| [i; 32];
+        assert_eq!(merkle_root(&[]), EMPTY_ROOT);
+        for n in 1..12u8 {
+            let leaves: Vec<[u8; 32]> = (0..n).map(leaf).collect();
+            let root = merkle_root(&leaves);
+            for i in 0..n as usize {
+                let proof = merkle_proof(&leaves, i).unwrap();
+                assert!(verify_proof(&root, &leaves[i], &proof));
+                assert!(!verify_proof(&root, &leaf(100), &proof));
+            }
+            assert!(merkle_proof(&leaves, n as usize).is_none());
+
+            // another list of leaves has another root, e.g. with the last one duplicated.
+            let mut duplicated = leaves.clone();
+            duplicated.push(leaves[n as usize - 1]);
+            assert_ne!(merkle_root(&duplicated), root);
+        }
+
+        // a block whose payload is changed on the way is rejected before reaching the dag.
+        let node = Node::init("node");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+        let coinbase = |id: &str| Transaction{id: String::from(id), inputs: Vec::new(), outputs: vec![TxOut{owner: String::from("alice"), amount: 10}]};
+
+        let mut template = node_w.build_block_template(&mut TopScore{n: 4});
+        template.txs = vec![coinbase("cb1"), coinbase("cb2")];
+        let header = template.header("A");
+        assert_eq!(header.merkle_root, tx_merkle_root(&header.txs));
+        assert_ne!(header_hash(&header), header_hash(&BlockRaw{merkle_root: EMPTY_ROOT, ..header.clone()}));
+
+        let tampered = BlockRaw{txs: vec![coinbase("cb1"), coinbase("cb3")], ..header.clone()};
+        assert_eq!(node_w.submit_block(&tampered, 3), Err(BlockError::BadMerkleRoot(String::from("A"))));
+        assert!(!node_add_raw(&tampered, &mut node_w, 3));
+        let mut stash = BlockStash::new(StashConfig::default());
+        handle_message(Message::Block(tampered), &mut node_w, &mut stash, 3, 0);
+        assert!(!node_w.dag.contains_key("A"));
+
+        node_w.submit_block(&header, 3).unwrap();
+        let block = node_w.dag.get("A").unwrap().read().unwrap();
+        assert_eq!(block.to_raw().merkle_root, header.merkle_root);
+        let proof = merkle_proof(&block.txs.iter().map(|tx| tx.hash()).collect::<Vec<_>>(), 1).unwrap();
+        assert!(verify_proof(&block.merkle_root, &coinbase("cb2").hash(), &proof));
+    }
+
+    #[test]
+    fn test_block_signatures() {
+
+        let _ = env_logger::try_init();
+
+        let node = Node::init("node");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+
+        let alice = MinerKey::from_seed(&[1u8; 32]);
+        let bob = MinerKey::from_seed(&[2u8; 32]);
+        let mut header = node_w.build_block_template(&mut TopScore{n: 4}).header("A");
+        alice.sign(&mut header);
+        assert_eq!(header.miner, Some(alice.public_key()));
+        assert_eq!(check_signature(&header), Ok(()));
+
+        // a signature over another header, by another key, or without key, is rejected on ingest.
+        let mut forged = BlockRaw{miner: Some(bob.public_key()), ..header.clone()};
+        assert_eq!(node_w.submit_block(&forged, 3), Err(BlockError::BadSignature(String::from("A"))));
+        forged = BlockRaw{timestamp: 1, ..header.clone()};
+        assert_eq!(check_signature(&forged), Err(BlockError::BadSignature(String::from("A"))));
+        forged = BlockRaw{miner: None, ..header.clone()};
+        assert_eq!(check_signature(&forged), Err(BlockError::BadSignature(String::from("A"))));
+        assert!(!node_add_raw(&forged, &mut node_w, 3));
+        node_w.submit_block(&header, 3).unwrap();
+        assert_eq!(node_w.dag.get("A").unwrap().read().unwrap().to_raw(), header);
+
+        // the miner key of the node signs its blocks.
+        node_w.miner_key = Some(bob.clone());
+        let raw = node_w.mine_block("B", &mut TopScore{n: 4}, 3).unwrap();
+        assert_eq!(raw.miner, Some(bob.public_key()));
+        assert_eq!(check_signature(&raw), Ok(()));
+
+        // in simulation, the per-miner statistics sum up to the signed blocks, the same on all the nodes.
+        let config = SimConfig{nodes: 8, blocks: 200, k: 3, latency: Latency::Exponential(300), seed: 3, signatures: true, ..SimConfig::default()};
+        let mut sim = Simulator::new(config);
+        sim.run();
+        for node in &sim.nodes {
+            let node_r = node.read().unwrap();
+            let stats = node_r.miner_stats();
+            assert_eq!(stats.values().map(|miner| miner.blocks).sum::<u64>(), 200);
+            for miner in &sim.nodes {
+                let miner_r = miner.read().unwrap();
+                let blocks = stats.get(&miner_r.miner_key.as_ref().unwrap().public_key()).map_or(0, |miner| miner.blocks);
+                assert_eq!(blocks, miner_r.mined_blocks);
+            }
+            for miner in stats.values() {
+                assert_eq!(miner.blue + miner.red, miner.blocks);
+            }
+        }
+    }
+
+    #[test]
+    fn test_rewards() {
+
+        let _ = env_logger::try_init();
+
+        let config = SimConfig{nodes: 10, blocks: 200, k: 1, latency: Latency::Exponential(400), seed: 5, signatures: true, ..SimConfig::default()};
+        let mut sim = Simulator::new(config);
+        sim.run();
+
+        // replay the blocks of a node in its arrival order, the rewards following the colours after each block.
+        let mut raws: Vec<(u64, BlockRaw)> = sim.nodes[2].read().unwrap().dag.values()
+            .map(|block| block.read().unwrap()).filter(|block| block.name != "Genesis")
+            .map(|block| (block.sequence, block.to_raw())).collect();
+        raws.sort_by_key(|&(sequence,_)| sequence);
+
+        let node = Node::init("replay");
+        let mut node_w = node.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 1, true);
+
+        let reward_config = RewardConfig{block_reward: 1000, red_reward_percent: 20, merge_fee: 50};
+        let mut rewards = Rewards::new(reward_config.clone());
+        let mut lagging = Rewards::new(reward_config.clone());
+        node_w.max_colour_log = 40;
+        for (i, (_, raw)) in raws.iter().enumerate() {
+            assert!(node_add_raw(raw, &mut node_w, 1));
+            rewards.update(&node_w);
+            let expected = rewards_from_node(&node_w, reward_config.clone());
+            assert_eq!(rewards.blocks, expected.blocks);
+            assert_eq!(rewards.miners, expected.miners);
+
+            // an update behind the kept colour log checks all the blocks.
+            if i % 30 == 29 {
+                assert!(node_w.colour_log_since(0).is_none());
+                assert_eq!(lagging.update(&node_w), node_w.dag.len());
+                assert_eq!(lagging.blocks, expected.blocks);
+            }
+        }
+        assert!(node_w.colour_log_end() > raws.len() + 1);
+        assert!(node_w.colour_log.len() <= 40);
+
+        // blue blocks get the full reward, red ones 20%, and each red block with a child pays one merge fee.
+        let blocks: Vec<(bool, bool)> = node_w.dag.values().map(|block| block.read().unwrap())
+            .filter(|block| block.name != "Genesis").map(|block| (block.is_blue, !block.next.is_empty())).collect();
+        let blue = blocks.iter().filter(|&&(is_blue,_)| is_blue).count() as u64;
+        let red = blocks.len() as u64 - blue;
+        let merged = blocks.iter().filter(|&&(is_blue, has_child)| !is_blue && has_child).count() as u64;
+        println!("test_rewards(): blue={}, red={}, merged={}", blue, red, merged);
+        assert!(red > 0 && merged > 0);
+        assert_eq!(rewards.total(), blue * 1000 + red * 200 + merged * 50);
+        assert_eq!(rewards.miners.values().sum::<u64>(), rewards.total());
+
+        // the miner rewards match their block stats.
+        let stats = node_w.miner_stats();
+        for (miner, reward) in &rewards.miners {
+            let miner_stats = &stats[miner];
+            assert!(*reward >= miner_stats.blue * 1000 + miner_stats.red * 200);
+        }
+    }
+
+    #[test]
+    fn test_virtual_block() {
+
+        let _ = env_logger::try_init();
+
+        let node = Node::init("node");
+        let mut node_w = node.write().unwrap();
+        assert_eq!(node_w.virtual_selected_parent(), None);
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+        assert_eq!(node_w.virtual_selected_parent(), Some("Genesis"));
+        assert_eq!((node_w.virtual_past_size(), node_w.virtual_blue_score(), node_w.virtual_block.height), (1, 1, 1));
+
+        // the virtual block sees the whole dag, whatever the arrival order of the blocks.
+        let config = SimConfig{nodes: 10, blocks: 200, k: 3, latency: Latency::Exponential(300), seed: 9, ..SimConfig::default()};
+        let mut sim = Simulator::new(config);
+        sim.run();
+        for node in &sim.nodes {
+            let node_r = node.read().unwrap();
+            let virtual_block = &node_r.virtual_block;
+            let blues = node_r.dag.values().filter(|block| block.read().unwrap().is_blue).count() as u64;
+            let score_stpq = get_stpq(&node_r.tips);
+
+            assert_eq!(node_r.virtual_past_size(), node_r.dag.len() as u64);
+            assert_eq!(node_r.virtual_blue_score(), blues);
+            assert_eq!(node_r.virtual_selected_parent(), Some(score_stpq[0].0.as_str()));
+            assert_eq!(virtual_block.parents, score_stpq.iter().map(|(name,_,_)| name.clone()).collect::<Vec<_>>());
+            assert_eq!(virtual_block.height, node_r.height + 1);
+            for name in &virtual_block.blue_parents {
+                assert!(node_r.tips[name].read().unwrap().is_blue);
+            }
+            assert_eq!(*virtual_block, VirtualBlock::from_tips(&node_r));
+
+            // its blue set and its order cover the whole dag.
+            let blue_set = node_r.virtual_blue_set();
+            assert_eq!(blue_set.len() as u64, blues);
+            assert!(blue_set.iter().all(|name| node_r.dag[name].read().unwrap().is_blue));
+            let order = node_r.virtual_order();
+            assert_eq!(order, dag_order(&node_r.dag));
+            assert_eq!(order.len(), node_r.dag.len());
+            assert_eq!(order[0], "Genesis");
+        }
+    }
+
+    #[test]
+    fn test_selected_chain() {
+
+        let _ = env_logger::try_init();
+
+        let full_chain = |node: &Node| {
+            let mut chain: Vec<String> = Vec::new();
+            let mut cursor = node.virtual_selected_parent().map(String::from);
+            while let Some(name) = cursor {
+                cursor = node.selected_parent(&name);
+                chain.push(name);
+            }
+            chain
+        };
+
+        let config = SimConfig{nodes: 10, blocks: 300, k: 3, latency: Latency::Exponential(400), seed: 13, ..SimConfig::default()};
+        let mut sim = Simulator::new(config);
+        sim.run();
+        for node in &sim.nodes {
+            let node_r = node.read().unwrap();
+            let chain = node_r.selected_chain();
+            assert_eq!(chain, full_chain(&node_r));
+            assert_eq!(chain.last().map(|name| name.as_str()), Some("Genesis"));
+            assert_eq!(node_r.selected_parent("Genesis"), None);
+        }
+
+        // replay the blocks of a node in its arrival order, a subscriber following the chain from the notifications.
+        let mut raws: Vec<(u64, BlockRaw)> = sim.nodes[4].read().unwrap().dag.values()
+            .map(|block| block.read().unwrap()).filter(|block| block.name != "Genesis")
+            .map(|block| (block.sequence, block.to_raw())).collect();
+        raws.sort_by_key(|&(sequence,_)| sequence);
+
+        let node = Node::init("replay");
+        let mut node_w = node.write().unwrap();
+        let rx = node_w.subscribe_chain();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+
+        let mut chain: Vec<String> = Vec::new();
+        let mut switches = 0;
+        for (_, raw) in &raws {
+            assert!(node_add_raw(raw, &mut node_w, 3));
+            for change in rx.try_iter() {
+                for name in &change.removed {
+                    assert_eq!(chain.pop().as_ref(), Some(name));
+                }
+                if !change.removed.is_empty() {
+                    switches += 1;
+                }
+                chain.extend(change.added);
+            }
+            assert_eq!(chain.iter().rev().cloned().collect::<Vec<_>>(), full_chain(&node_w));
+        }
+        println!("test_selected_chain(): chain length {}, {} switches", chain.len(), switches);
+        assert!(switches > 0);
+        drop(node_w);
+
+        // a new score of a block below the top of the chain (as set by 'calc_blue' step 9) switches the chain from there.
+        let node = Node::init("deep");
+        let mut node_w = node.write().unwrap();
+        let rx = node_w.subscribe_chain();
+        node_add_block("Genesis", &Vec::new(), &mut node_w, 3, true);
+        node_add_block("B", &vec!["Genesis"], &mut node_w, 3, true);
+        node_add_block("C", &vec!["Genesis"], &mut node_w, 3, true);
+        node_add_block("D", &vec!["B", "C"], &mut node_w, 3, true);
+        node_add_block("E", &vec!["D"], &mut node_w, 3, true);
+        assert_eq!(node_w.selected_chain(), vec!["E", "D", "B", "Genesis"]);
+        let _ = rx.try_iter().count();
+
+        node_w.dag["C"].write().unwrap().size_of_past_blue += 1;
+        node_w.rescored.push(String::from("C"));
+        node_add_block("F", &vec!["E"], &mut node_w, 3, true);
+        assert_eq!(node_w.selected_chain(), vec!["F", "E", "D", "C", "Genesis"]);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![ChainChanged{removed: vec![String::from("E"), String::from("D"), String::from("B")],
+            added: vec![String::from("C"), String::from("D"), String::from("E"), String::from("F")]}]);
+    }
+
+    #[test]
+    fn test_ghostdag_vs_calc_blue() {
+
+        let _ = env_logger::try_init();
+
+        // the largest blue anticone among the blues: within K for a K-cluster.
+        let max_blue_anticone = |node: &Node, blues: &HashSet<String>| {
+            let mut names: Vec<(u64, String)> = node.dag.values().map(|block| block.read().unwrap()).map(|block| (block.size_of_past_set, block.name.clone())).collect();
+            names.sort();
+            let mut past: HashMap<String, HashSet<String>> = HashMap::new();
+            for (_, name) in &names {
+                let mut block_past: HashSet<String> = HashSet::new();
+                for prev in node.dag[name].read().unwrap().prev.keys() {
+                    block_past.insert(prev.clone());
+                    block_past.extend(past[prev].iter().cloned());
+                }
+                past.insert(name.clone(), block_past);
+            }
+            blues.iter().map(|blue| blues.iter().filter(|other| *other != blue && !past[blue].contains(*other) && !past[*other].contains(blue)).count()).max().unwrap_or(0)
+        };
+
+        // colour the same blocks, in the same arrival order, with both engines.
+        let compare = |title: &str, blocks: &[(String, Vec<String>)], k: i32| {
+            let mut blue_sets: Vec<HashSet<String>> = Vec::new();
+            let mut engines: Vec<Box<dyn ColouringEngine>> = vec![Box::new(CalcBlue), Box::new(Ghostdag::new())];
+            for engine in engines.iter_mut() {
+                let node = Node::init(engine.name());
+                let mut node_w = node.write().unwrap();
+                node_add_block_with(&mut **engine, "Genesis", &Vec::new(), &mut node_w, k).unwrap();
+                for (name, references) in blocks {
+                    let references: Vec<&str> = references.iter().map(|reference| reference.as_str()).collect();
+                    node_add_block_with(&mut **engine, name, &references, &mut node_w, k).unwrap();
+                }
+                let blues = engine.blue_set(&node_w, k).unwrap();
+                assert!(blues.contains("Genesis"));
+                if engine.name() == "ghostdag" {
+                    assert!(max_blue_anticone(&node_w, &blues) <= k as usize);
+                }
+                println!("{}: k={}, {}: {} blues of {} blocks, max blue anticone {}", title, k, engine.name(), blues.len(), node_w.dag.len(), max_blue_anticone(&node_w, &blues));
+                blue_sets.push(blues);
+            }
+
+            let mut only_calc_blue: Vec<&String> = blue_sets[0].difference(&blue_sets[1]).collect();
+            let mut only_ghostdag: Vec<&String> = blue_sets[1].difference(&blue_sets[0]).collect();
+            only_calc_blue.sort();
+            only_ghostdag.sort();
+            println!("{}: blue for calc_blue only {:?}, for ghostdag only {:?}", title, only_calc_blue, only_ghostdag);
+            blue_sets
+        };
+
+        let owned = |blocks: Vec<(&str, Vec<&str>)>| blocks.into_iter().map(|(name, references)| (name.to_string(), references.into_iter().map(String::from).collect())).collect::<Vec<_>>();
+        let fig3 = owned(vec![
+            ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+            ("F", vec!["B","C"]), ("H", vec!["C","D","E"]), ("I", vec!["E"]),
+            ("J", vec!["F","H"]), ("K", vec!["B","H","I"]), ("L", vec!["D","I"]), ("N", vec!["L","K"]), ("M", vec!["F","K"]),
+        ]);
+        let fig4 = owned(vec![
+            ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+            ("F", vec!["B","C"]), ("H", vec!["E"]), ("I", vec!["C","D"]),
+            ("J", vec!["F","D"]), ("K", vec!["J","I","E"]), ("L", vec!["F"]), ("N", vec!["D","H"]),
+            ("M", vec!["L","K"]), ("O", vec!["K"]), ("P", vec!["K"]), ("Q", vec!["N"]),
+            ("R", vec!["O","P","N"]), ("S", vec!["Q"]), ("T", vec!["S"]), ("U", vec!["T"]),
+        ]);
+
+        // on the figures, both engines keep the honest cluster blue and the isolated chain (Q, S, T, U of fig4) red.
+        // they agree on fig4. on fig3, calc_blue takes B, F, J, with a blue anticone of 4 > K, where ghostdag takes E, I.
+        let blues = compare("fig3", &fig3, 3);
+        for blues in &blues {
+            for name in &["Genesis", "C", "D", "H", "K"] {
+                assert!(blues.contains(*name));
+            }
+        }
+        let blues = compare("fig4", &fig4, 3);
+        for blues in &blues {
+            for name in &["Genesis", "C", "D", "I", "J", "K", "O", "P", "R"] {
+                assert!(blues.contains(*name));
+            }
+            for name in &["Q", "S", "T", "U"] {
+                assert!(!blues.contains(*name));
+            }
+        }
+
+        // random dags from simulations: the engines colour about as many blocks, but they often pick different ones
+        // among concurrent blocks, and the blue set of calc_blue may exceed K (a blue anticone of 9 for K=5 with seed 4).
+        for &(k, latency, seed) in &[(3, 300, 1), (3, 1_000, 2), (1, 500, 3), (5, 2_000, 4)] {
+            let config = SimConfig{nodes: 8, blocks: 150, k, latency: Latency::Exponential(latency), seed, ..SimConfig::default()};
+            let mut sim = Simulator::new(config);
+            sim.run();
+
+            let node_r = sim.nodes[0].read().unwrap();
+            let mut blocks: Vec<(u64, String, Vec<String>)> = node_r.dag.values().map(|block| block.read().unwrap())
+                .filter(|block| block.name != "Genesis").map(|block| (block.sequence, block.name.clone(), block.prev.keys().cloned().collect())).collect();
+            blocks.sort();
+            let blocks: Vec<(String, Vec<String>)> = blocks.into_iter().map(|(_, name, references)| (name, references)).collect();
+
+            let blue_sets = compare(&format!("random seed {}", seed), &blocks, k);
+            let (calc_blues, ghostdag_blues) = (blue_sets[0].len() as f64, blue_sets[1].len() as f64);
+            assert!((calc_blues - ghostdag_blues).abs() <= 0.2 * ghostdag_blues);
+        }
+
+        // the ghostdag engine keeps the tips snapshot, the virtual block and the selected chain of the node up to date too.
+        let mut ghostdag = Ghostdag::new();
+        let node = Node::init("ghostdag");
+        let mut node_w = node.write().unwrap();
+        node_add_block_with(&mut ghostdag, "Genesis", &Vec::new(), &mut node_w, 3).unwrap();
+        node_add_block_with(&mut ghostdag, "B", &vec!["Genesis"], &mut node_w, 3).unwrap();
+        assert_eq!(node_w.virtual_block.selected_parent, Some(String::from("B")));
+        assert_eq!(node_w.chain, vec!["Genesis", "B"]);
+        assert!(node_w.dag["B"].read().unwrap().tips_snapshot.contains_key("B"));
+        assert_eq!(node_add_block_with(&mut ghostdag, "B", &vec!["Genesis"], &mut node_w, 3), Err(ColouringError::Duplicate(String::from("B"))));
+        drop(node_w);
+
+        // a dag not coloured by the engine, e.g. loaded from a dump, gives an error instead of a panic.
+        let other = Node::init("other");
+        let mut other_w = other.write().unwrap();
+        node_add_block("Genesis", &Vec::new(), &mut other_w, 3, true);
+        node_add_block("B", &vec!["Genesis"], &mut other_w, 3, true);
+        let mut ghostdag = Ghostdag::new();
+        assert!(ghostdag.blue_set(&other_w, 3).is_err());
+        assert_eq!(node_add_block_with(&mut ghostdag, "C", &vec!["B"], &mut other_w, 3), Err(ColouringError::MissingData(String::from("B"))));
+        assert!(other_w.dag.contains_key("C"));
+    }
 }
@@ -17,10 +17,455 @@
 #[macro_use]
 extern crate log;
 extern crate env_logger;
+extern crate rand;
+extern crate blockdag;
+
+use std::env;
+use std::process;
+use std::fs::File;
+use std::io::Write;
+use std::thread;
+use std::time::{Duration,Instant};
+use rand::Rng;
+
+use blockdag::blockdag::{Node,node_add_block,get_stpq,dag_render_to_file,node_load_json};
+use blockdag::blockdag::{Simulator,SimConfig,PowConfig,Latency,Topology,sim_metrics,AttackerConfig,AttackRelease,sim_attack_report};
+use blockdag::blockdag::{PartitionConfig,PartitionCut,sim_partition_report};
+use blockdag::blockdag::{NetNode,Relay,ParentStrategy,TimeRules,node_save_json};
+
+const USAGE: &str = "usage:
+    godag render <output.svg|output.html> [fig3|fig4|random|dump.json] [--blocks N] [--k K]
+    godag simulate [--nodes N] [--blocks N] [--k K] [--interval MS] [--latency MIN-MAX] [--topology full|regular:D|smallworld:D|regions:R] [--seed S] [--attack FRACTION:BLOCKS] [--split START:DURATION[:drop]] [--relay push|inv] [--parents k1|top:N|all|blue|random:N[:max:M]] [--pow HASHES_PER_MS] [--sign] [--csv FILE] [--json FILE]
+    godag node [--listen ADDR] [--peer ADDR]... [--name NAME] [--k K] [--interval MS] [--blocks N] [--duration SECS] [--dump FILE]";
+
+const CONNECT_RETRY: u32 = 20;                              // a peer may be starting as well, retry every 500ms.
+
 
 fn main() {
     env_logger::init();
 
     info!("starting up");
-    println!("Hello, world!");
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(|s| s.as_str()) {
+        Some("render") => render(&args[1..]),
+        Some("simulate") => simulate(&args[1..]),
+        Some("node") => run_node(&args[1..]),
+        _ => {
+            println!("{}", USAGE);
+            process::exit(1);
+        },
+    }
+}
+
+/// 'godag render': build an example dag, or load a JSON dump of a node, and write it as a SVG or HTML picture.
+///
+fn render(args: &[String]) {
+
+    let mut output: Option<String> = None;
+    let mut example = String::from("fig4");
+    let mut blocks: u64 = 100;
+    let mut k: i32 = 3;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--blocks" => {
+                i += 1;
+                blocks = parse_arg(args.get(i), "--blocks");
+            },
+            "--k" => {
+                i += 1;
+                k = parse_arg(args.get(i), "--k");
+            },
+            other => {
+                if output.is_none() {
+                    output = Some(other.to_string());
+                }else{
+                    example = other.to_string();
+                }
+            },
+        }
+        i += 1;
+    }
+
+    let output = output.unwrap_or_else(|| {
+        println!("{}", USAGE);
+        process::exit(1);
+    });
+
+    let node = if example.ends_with(".json") {
+        node_load_json(&example).unwrap_or_else(|e| {
+            println!("failed to load {}: {}", example, e);
+            process::exit(1);
+        })
+    }else{
+        Node::init(&example)
+    };
+    let mut node_w = node.write().unwrap();
+
+    match example.as_str() {
+        _ if example.ends_with(".json") => {},
+        "fig3" => build_fig3(&mut node_w),
+        "fig4" => build_fig4(&mut node_w),
+        "random" => build_random(&mut node_w, blocks, k),
+        _ => {
+            println!("unknown example: {}\n{}", example, USAGE);
+            process::exit(1);
+        },
+    }
+
+    match dag_render_to_file(&node_w, &output) {
+        Ok(()) => println!("{} written. {}", output, &*node_w),
+        Err(e) => {
+            println!("failed to write {}: {}", output, e);
+            process::exit(1);
+        },
+    }
+}
+
+/// 'godag simulate': run a network simulation and write the metrics report as CSV or JSON, for plotting.
+///
+fn simulate(args: &[String]) {
+
+    let mut config = SimConfig::default();
+    let mut csv: Option<String> = None;
+    let mut json: Option<String> = None;
+    let mut hash_rate: Option<f64> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        i += 1;
+        match flag {
+            "--nodes" => config.nodes = parse_arg(args.get(i), flag),
+            "--blocks" => config.blocks = parse_arg(args.get(i), flag),
+            "--k" => config.k = parse_arg(args.get(i), flag),
+            "--interval" => config.block_interval = parse_arg(args.get(i), flag),
+            "--seed" => config.seed = parse_arg(args.get(i), flag),
+            "--latency" => {
+                let range: Vec<u64> = args.get(i).map_or(Vec::new(), |s| s.split('-').filter_map(|v| v.parse().ok()).collect());
+                config.latency = match range.len() {
+                    1 => Latency::Constant(range[0]),
+                    2 => Latency::Uniform(range[0], range[1]),
+                    _ => invalid_arg(flag),
+                };
+            },
+            "--topology" => {
+                let spec = args.get(i).cloned().unwrap_or_default();
+                let mut parts = spec.splitn(2, ':');
+                let kind = parts.next().unwrap_or("");
+                let param: Option<usize> = parts.next().and_then(|p| p.parse().ok());
+                config.topology = match (kind, param) {
+                    ("full", _) => Topology::FullMesh,
+                    ("regular", Some(degree)) => Topology::RandomRegular(degree),
+                    ("smallworld", Some(degree)) => Topology::SmallWorld{degree, rewire: 0.1},
+                    ("regions", Some(regions)) => Topology::Regions{regions, inter_links: 2, inter_latency: Latency::Uniform(500, 1500)},
+                    _ => invalid_arg(flag),
+                };
+            },
+            "--attack" => {
+                let spec = args.get(i).cloned().unwrap_or_default();
+                let mut parts = spec.splitn(2, ':');
+                let fraction: Option<f64> = parts.next().and_then(|p| p.parse().ok());
+                let blocks: Option<u64> = parts.next().and_then(|p| p.parse().ok());
+                config.attacker = match (fraction, blocks) {
+                    (Some(hash_fraction), Some(blocks)) => Some(AttackerConfig{hash_fraction, release: AttackRelease::AfterBlocks(blocks)}),
+                    _ => invalid_arg(flag),
+                };
+            },
+            "--split" => {
+                let spec = args.get(i).cloned().unwrap_or_default();
+                let parts: Vec<&str> = spec.split(':').collect();
+                let start: Option<u64> = parts.first().and_then(|p| p.parse().ok());
+                let duration: Option<u64> = parts.get(1).and_then(|p| p.parse().ok());
+                let cut = match parts.get(2) {
+                    None => PartitionCut::Delay,
+                    Some(&"drop") => PartitionCut::Drop,
+                    Some(_) => invalid_arg(flag),
+                };
+                config.partition = match (start, duration) {
+                    (Some(start), Some(duration)) => Some(PartitionConfig::halves(0, start, duration, cut)),
+                    _ => invalid_arg(flag),
+                };
+            },
+            "--relay" => {
+                config.relay = match args.get(i).map(|s| s.as_str()) {
+                    Some("push") => Relay::Push,
+                    Some("inv") => Relay::Inventory,
+                    _ => invalid_arg(flag),
+                };
+            },
+            "--parents" => config.parents = parse_parents(args.get(i)).unwrap_or_else(|| invalid_arg(flag)),
+            "--pow" => hash_rate = Some(parse_arg(args.get(i), flag)),
+            "--sign" => {
+                config.signatures = true;
+                i -= 1;                                     // no value.
+            },
+            "--csv" => csv = Some(parse_arg(args.get(i), flag)),
+            "--json" => json = Some(parse_arg(args.get(i), flag)),
+            _ => {
+                println!("unknown option: {}\n{}", flag, USAGE);
+                process::exit(1);
+            },
+        }
+        i += 1;
+    }
+
+    // the cut is in the middle of the nodes, whatever the options order.
+    if let Some(ref mut partition) = config.partition {
+        partition.side_a = config.nodes / 2;
+    }
+    // each node with this hash rate, the difficulty adjustment targets '--interval'.
+    config.pow = hash_rate.map(|rate| PowConfig::uniform(config.nodes, rate, config.block_interval));
+
+    let mut sim = Simulator::new(config);
+    sim.run();
+
+    let metrics = sim_metrics(&sim);
+    println!("{:?}", metrics.aggregated);
+    println!("{:?}", metrics.traffic);
+    if let Some(report) = sim_attack_report(&sim) {
+        println!("{}", report);
+    }
+    if let Some(report) = sim_partition_report(&sim) {
+        println!("{}", report);
+    }
+    if sim.config.pow.is_some() && sim.mined.len() > 2 {
+        let half = &sim.mined[sim.mined.len()/2..];
+        let interval = (half[half.len()-1].2 - half[0].2) as f64 / (half.len()-1) as f64;
+        println!("pow: block interval over the last {} blocks: {:.1}ms", half.len(), interval);
+    }
+    // blocks of each miner, with the colours of the first node.
+    if sim.config.signatures {
+        let stats = sim.nodes[0].read().unwrap().miner_stats();
+        for node in &sim.nodes {
+            let node_r = node.read().unwrap();
+            let miner = node_r.miner_key.as_ref().and_then(|key| stats.get(&key.public_key()));
+            if let Some(miner) = miner {
+                println!("miner {}: blocks={}, blue={}, red={}", node_r.name, miner.blocks, miner.blue, miner.red);
+            }
+        }
+    }
+
+    if let Some(path) = csv {
+        write_file(&path, &metrics.to_csv());
+    }
+    if let Some(path) = json {
+        write_file(&path, &metrics.to_json());
+    }
+}
+
+/// 'godag node': run a node on the network. It mines a block every 'interval' ms in average, up to 'blocks' blocks,
+/// and prints its state every second. Several of them on localhost converge to the same dag.
+///
+fn run_node(args: &[String]) {
+
+    let mut listen = String::from("127.0.0.1:7000");
+    let mut peers: Vec<String> = Vec::new();
+    let mut name: Option<String> = None;
+    let mut k: i32 = 3;
+    let mut interval: u64 = 2000;
+    let mut blocks: u64 = 100;
+    let mut duration: u64 = 0;
+    let mut dump: Option<String> = None;
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        i += 1;
+        match flag {
+            "--listen" => listen = parse_arg(args.get(i), flag),
+            "--peer" => peers.push(parse_arg(args.get(i), flag)),
+            "--name" => name = Some(parse_arg(args.get(i), flag)),
+            "--k" => k = parse_arg(args.get(i), flag),
+            "--interval" => interval = parse_arg(args.get(i), flag),
+            "--blocks" => blocks = parse_arg(args.get(i), flag),
+            "--duration" => duration = parse_arg(args.get(i), flag),
+            "--dump" => dump = Some(parse_arg(args.get(i), flag)),
+            _ => {
+                println!("unknown option: {}\n{}", flag, USAGE);
+                process::exit(1);
+            },
+        }
+        i += 1;
+    }
+
+    let name = name.unwrap_or_else(|| format!("node-{}", listen.rsplit(':').next().unwrap_or("0")));
+    let node = Node::init(&name);
+    node_add_block("Genesis", &Vec::new(), &mut node.write().unwrap(), k, true);
+    node.write().unwrap().time_rules = Some(TimeRules::default());
+
+    let net = NetNode::start(node, k, &listen).unwrap_or_else(|e| {
+        println!("failed to listen on {}: {}", listen, e);
+        process::exit(1);
+    });
+    println!("{} listening on {}", name, net.local_addr());
+
+    for peer in &peers {
+        let mut attempt = 0;
+        while let Err(e) = net.connect(peer) {
+            attempt += 1;
+            if attempt >= CONNECT_RETRY {
+                println!("failed to connect to {}: {}", peer, e);
+                break;
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+    }
+
+    let started = Instant::now();
+    let mut rng = rand::thread_rng();
+    let mut mined: u64 = 0;
+    let mut next_block = Instant::now();
+    let mut next_status = Instant::now();
+
+    while duration == 0 || started.elapsed() < Duration::from_secs(duration) {
+        let now = Instant::now();
+
+        if interval > 0 && mined < blocks && now >= next_block {
+            mined += 1;
+            if let Err(e) = net.mine(&format!("{}-{:04}", name, mined)) {
+                println!("{}: block not mined. {}", name, e);
+            }
+            let uniform: f64 = 1.0 - rng.gen::<f64>();
+            next_block = now + Duration::from_millis((-uniform.ln() * interval as f64) as u64);
+        }
+
+        if now >= next_status {
+            let node = net.node();
+            let node_r = node.read().unwrap();
+            let blues = node_r.dag.values().filter(|block| block.read().unwrap().is_blue).count();
+            println!("{}: peers={},size_of_dag={},height={},tips={},blues={},mined={}",
+                     name, net.peers(), node_r.size_of_dag, node_r.height, node_r.tips.len(), blues, node_r.mined_blocks);
+            next_status = now + Duration::from_secs(1);
+        }
+
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    if let Some(path) = dump {
+        let node = net.node();
+        let result = node_save_json(&node.read().unwrap(), k, &path);
+        match result {
+            Ok(()) => println!("{} written.", path),
+            Err(e) => println!("failed to write {}: {}", path, e),
+        }
+    }
+}
+
+/// 'k1', 'top:N', 'all', 'blue' or 'random:N', optionally followed by ':max:M' for a cap on the references.
+///
+fn parse_parents(arg: Option<&String>) -> Option<ParentStrategy> {
+
+    let spec = arg?;
+    let (strategy, max) = match spec.find(":max:") {
+        Some(pos) => (&spec[..pos], Some(spec[pos+5..].parse::<usize>().ok()?)),
+        None => (spec.as_str(), None),
+    };
+
+    let mut parts = strategy.splitn(2, ':');
+    let kind = parts.next().unwrap_or("");
+    let param: Option<usize> = parts.next().and_then(|p| p.parse().ok());
+    let strategy = match (kind, param) {
+        ("k1", None) => ParentStrategy::KPlusOne,
+        ("top", Some(n)) => ParentStrategy::TopScore(n),
+        ("all", None) => ParentStrategy::AllTips,
+        ("blue", None) => ParentStrategy::BlueTips,
+        ("random", Some(n)) => ParentStrategy::RandomSubset(n),
+        _ => return None,
+    };
+
+    Some(match max {
+        Some(max) => ParentStrategy::MaxParents(Box::new(strategy), max),
+        None => strategy,
+    })
+}
+
+fn write_file(path: &str, content: &str) {
+    let result = File::create(path).and_then(|mut file| file.write_all(content.as_bytes()));
+    if let Err(e) = result {
+        println!("failed to write {}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(arg: Option<&String>, flag: &str) -> T {
+    match arg.and_then(|s| s.parse().ok()) {
+        Some(value) => value,
+        None => invalid_arg(flag),
+    }
+}
+
+fn invalid_arg(flag: &str) -> ! {
+    println!("invalid value for {}\n{}", flag, USAGE);
+    process::exit(1);
+}
+
+fn build_fig3(node: &mut Node) {
+
+    let k: i32 = 3;
+    let blocks: Vec<(&str, Vec<&str>)> = vec![
+        ("Genesis", vec![]),
+        ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+        ("F", vec!["B","C"]), ("H", vec!["C","D","E"]), ("I", vec!["E"]),
+        ("J", vec!["F","H"]), ("K", vec!["B","H","I"]), ("L", vec!["D","I"]),
+        ("N", vec!["L","K"]), ("M", vec!["F","K"]),
+    ];
+
+    for (name, references) in &blocks {
+        node_add_block(name, references, node, k, true);
+    }
+}
+
+fn build_fig4(node: &mut Node) {
+
+    let k: i32 = 3;
+    let blocks: Vec<(&str, Vec<&str>)> = vec![
+        ("Genesis", vec![]),
+        ("B", vec!["Genesis"]), ("C", vec!["Genesis"]), ("D", vec!["Genesis"]), ("E", vec!["Genesis"]),
+        ("F", vec!["B","C"]), ("H", vec!["E"]), ("I", vec!["C","D"]),
+        ("J", vec!["F","D"]), ("K", vec!["J","I","E"]), ("L", vec!["F"]), ("N", vec!["D","H"]),
+        ("M", vec!["L","K"]), ("O", vec!["K"]), ("P", vec!["K"]), ("Q", vec!["N"]),
+        ("R", vec!["O","P","N"]),
+        ("S", vec!["Q"]), ("T", vec!["S"]), ("U", vec!["T"]),
+    ];
+
+    for (name, references) in &blocks {
+        node_add_block(name, references, node, k, true);
+    }
+}
+
+/// A random dag: every round mines 1..=k+1 parallel blocks, each referencing some of the best tips by score.
+///
+fn build_random(node: &mut Node, blocks: u64, k: i32) {
+
+    node_add_block("Genesis", &Vec::new(), node, k, true);
+
+    let mut rng = rand::thread_rng();
+    let mut generated: u64 = 0;
+    while generated < blocks {
+
+        let score_stpq = get_stpq(&node.tips);
+        let parallel = rng.gen_range(1, k+2);
+
+        let mut round: Vec<(String, Vec<String>)> = Vec::new();
+        for _ in 0..parallel {
+            if generated >= blocks {
+                break;
+            }
+            generated += 1;
+
+            let max_prev = rng.gen_range(1, score_stpq.len()+1);
+            let references = score_stpq.iter().take(max_prev).map(|(s,_,_)| s.clone()).collect::<Vec<String>>();
+            round.push((format!("{:04}", generated), references));
+        }
+
+        // blocks of one round don't see each other, as if they were mined in parallel.
+        for (name, references) in &round {
+            let references_str = references.iter().map(|s| s.as_str()).collect::<Vec<&str>>();
+            node_add_block(name, &references_str, node, k, true);
+        }
+    }
 }